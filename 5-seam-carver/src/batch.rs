@@ -0,0 +1,480 @@
+use crate::depth::{ColorPixel, DepthImage};
+use crate::error::{CarveError, CarveResult, PathContext};
+use crate::resize::{Carver, Pass, TargetSize};
+use crate::util::{ImageBufferVec, Kernel, KernelRect, Timer};
+use cgmath::Vector2;
+use glob::Pattern;
+use image::ImageFormat;
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// The output template used when none is specified.
+pub const DEFAULT_TEMPLATE: &str = "{stem}_{w}x{h}.{ext}";
+
+type PathFilter<'a> = Box<dyn Fn(&Path) -> bool + 'a>;
+
+/// Expands an input of a batch into the images it refers to. Directories expand to every image
+/// directly inside them and paths whose file name contains `*`, `?` or `[...]` wildcards expand to
+/// every matching file (see [Pattern]). Any other path is returned as is.
+pub fn find_inputs(pattern: &str) -> CarveResult<Vec<PathBuf>> {
+    let path = Path::new(pattern);
+    let (dir, filter): (&Path, PathFilter<'_>) = if path.is_dir() {
+        (path, Box::new(|path| ImageFormat::from_path(path).is_ok()))
+    } else {
+        match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) if name.contains(&['*', '?', '['][..]) => {
+                let pattern = Pattern::new(name).map_err(|err| {
+                    CarveError::InvalidInput(format!("Invalid pattern {:?}: {}", name, err))
+                })?;
+                let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty());
+                (
+                    dir.unwrap_or_else(|| Path::new(".")),
+                    Box::new(move |path| {
+                        path.file_name()
+                            .and_then(|name| name.to_str())
+                            .map_or(false, |file| pattern.matches(file))
+                    }),
+                )
+            }
+            _ => return Ok(vec![path.to_path_buf()]),
+        }
+    };
+
+    let mut inputs = Vec::new();
+    for entry in fs::read_dir(dir).reading(dir)? {
+        let path = entry.reading(dir)?.path();
+        if path.is_file() && filter(&path) {
+            inputs.push(path);
+        }
+    }
+
+    if inputs.is_empty() {
+        return Err(CarveError::InvalidInput(format!(
+            "No images match {:?}.",
+            pattern
+        )));
+    }
+
+    inputs.sort();
+    Ok(inputs)
+}
+
+/// Substitutes the placeholders of an output template: `{dir}`, `{stem}` and `{ext}` are the
+/// directory, file stem and extension of `input` and `{w}` and `{h}` are the output's size.
+pub fn format_output(template: &str, input: &Path, size: Vector2<i32>) -> PathBuf {
+    let part = |part: Option<&std::ffi::OsStr>| {
+        part.map_or_else(String::new, |part| part.to_string_lossy().into_owned())
+    };
+
+    let dir = match input.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => part(Some(dir.as_os_str())),
+        _ => ".".to_string(),
+    };
+
+    PathBuf::from(
+        template
+            .replace("{dir}", &dir)
+            .replace("{stem}", &part(input.file_stem()))
+            .replace("{ext}", &part(input.extension()))
+            .replace("{w}", &size.x.to_string())
+            .replace("{h}", &size.y.to_string()),
+    )
+}
+
+/// Checks that `template` tells the outputs of every input resized to every size apart, so that
+/// they don't overwrite each other. An empty `sizes` keeps the size of every input. Inputs whose
+/// size can't be read are skipped, as loading them fails anyway.
+pub fn check_template(template: &str, inputs: &[PathBuf], sizes: &[TargetSize]) -> CarveResult<()> {
+    if (inputs.len() > 1 && !template.contains("{stem}"))
+        || (sizes.len() > 1 && !(template.contains("{w}") || template.contains("{h}")))
+    {
+        return Err(CarveError::InvalidInput(format!(
+            "The output template {:?} must contain `{{stem}}` when resizing multiple images and \
+             `{{w}}` or `{{h}}` when resizing to multiple sizes.",
+            template
+        )));
+    }
+
+    let mut outputs = HashMap::<PathBuf, (&Path, Vector2<i32>)>::new();
+    for input in inputs {
+        let from_size = match image::image_dimensions(input) {
+            Ok((width, height)) => Vector2::new(width as i32, height as i32),
+            Err(_) => continue,
+        };
+
+        let mut to_sizes = sizes
+            .iter()
+            .map(|size| size.resolve(from_size))
+            .collect::<Vec<_>>();
+        if to_sizes.is_empty() {
+            to_sizes.push(from_size);
+        }
+
+        for size in to_sizes {
+            let output = format_output(template, input, size);
+            let (other, other_size) = *outputs.entry(output.clone()).or_insert((input, size));
+            if (other, other_size) != (input.as_path(), size) {
+                return Err(CarveError::InvalidInput(format!(
+                    "Both {:?} resized to {}x{} and {:?} resized to {}x{} would be saved to {:?} \
+                     by the output template {:?}.",
+                    other, other_size.x, other_size.y, input, size.x, size.y, output, template
+                )));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Resizes many images to many sizes in parallel. Every image is only loaded once and sizes which
+/// only shrink the same axis of an image are all produced by carving it once, saving every
+/// intermediate size on the way down.
+pub struct Batch<'a, F> {
+    pub inputs: &'a [PathBuf],
+    pub sizes: &'a [TargetSize],
+    pub template: &'a str,
+    /// Creates the carver used for every resize. Its size is overridden.
+    pub carver: F,
+}
+
+impl<F> Batch<'_, F>
+where
+    F: Fn() -> Carver<'static> + Sync,
+{
+    /// Runs the batch. Failures are reported alongside every other output rather than aborting the
+    /// batch.
+    pub fn run(&self) -> BatchReport {
+        let start = Instant::now();
+        let outputs = self
+            .inputs
+            .par_iter()
+            .flat_map(|input| self.run_image(input))
+            .collect();
+
+        BatchReport {
+            outputs,
+            elapsed: start.elapsed(),
+        }
+    }
+
+    fn run_image(&self, input: &Path) -> Vec<BatchOutput> {
+        let start = Instant::now();
+        match DepthImage::open(input) {
+            Ok(DepthImage::Rgba8(image)) => self.run_sizes(input, &image),
+            Ok(DepthImage::Rgba16(image)) => self.run_sizes(input, &image),
+            Ok(DepthImage::Rgba32F(image)) => self.run_sizes(input, &image),
+            Err(err) => vec![BatchOutput {
+                input: input.to_path_buf(),
+                size: None,
+                output: None,
+                elapsed: start.elapsed(),
+                result: Err(err),
+            }],
+        }
+    }
+
+    /// Resizes a loaded image to every size of the batch.
+    fn run_sizes<P: ColorPixel>(&self, input: &Path, image: &ImageBufferVec<P>) -> Vec<BatchOutput>
+    where
+        ImageBufferVec<P>: Sync,
+    {
+        let from_size = image.size();
+        let mut sizes = Vec::<Vector2<i32>>::new();
+        for size in self.sizes {
+            let size = size.resolve(from_size);
+            if !sizes.contains(&size) {
+                sizes.push(size);
+            }
+        }
+
+        // Group the sizes which can be snapshotted while carving towards the smallest of them.
+        let mut widths = Vec::new();
+        let mut heights = Vec::new();
+        let mut groups = Vec::new();
+        for &size in &sizes {
+            if size.y == from_size.y && size.x > 0 && size.x < from_size.x {
+                widths.push(size);
+            } else if size.x == from_size.x && size.y > 0 && size.y < from_size.y {
+                heights.push(size);
+            } else {
+                groups.push(vec![size]);
+            }
+        }
+
+        widths.sort_by_key(|size| -size.x);
+        heights.sort_by_key(|size| -size.y);
+        groups.extend(
+            [widths, heights]
+                .into_iter()
+                .filter(|group| !group.is_empty()),
+        );
+
+        let mut outputs = groups
+            .into_par_iter()
+            .flat_map(|group| self.run_group(input, image, &group))
+            .collect::<Vec<_>>();
+
+        outputs.sort_by_key(|output| sizes.iter().position(|size| Some(*size) == output.size));
+        outputs
+    }
+
+    /// Carves `image` down to the last size of `group`, saving the other sizes of the group when
+    /// the carve goes through them.
+    fn run_group<P: ColorPixel>(
+        &self,
+        input: &Path,
+        image: &ImageBufferVec<P>,
+        group: &[Vector2<i32>],
+    ) -> Vec<BatchOutput>
+    where
+        ImageBufferVec<P>: Sync,
+    {
+        let _timer = Timer::start("batch_resize");
+        let start = Instant::now();
+        let (&target, snapshots) = group.split_last().unwrap();
+
+        let save = |image: &ImageBufferVec<P>, size: Vector2<i32>, result: CarveResult<()>| {
+            let output = format_output(self.template, input, size);
+            let result = result.and_then(|_| save_image(image, &output));
+            BatchOutput {
+                input: input.to_path_buf(),
+                size: Some(size),
+                output: Some(output),
+                elapsed: start.elapsed(),
+                result,
+            }
+        };
+
+        let mut outputs = Vec::new();
+        let result = (self.carver)()
+            .size(target)
+            .on_pass(|pass| {
+                if let Pass::Carve(carve) = pass {
+                    let size = carve.image.size();
+                    if snapshots.contains(&size) {
+                        let snapshot =
+                            Kernel::from_fn(size, |pos| P::from_vec4(carve.image.color(pos)));
+                        outputs.push(save(&snapshot, size, Ok(())));
+                    }
+                }
+                Ok(())
+            })
+            .run(image.clone());
+
+        match result {
+            Ok(output) => outputs.push(save(&output.image, target, Ok(()))),
+            Err(err) => {
+                // Every size which wasn't reached is retried on its own so that it gets its own
+                // error.
+                let missed = snapshots
+                    .iter()
+                    .filter(|size| !outputs.iter().any(|output| output.size == Some(**size)))
+                    .copied()
+                    .collect::<Vec<_>>();
+
+                for size in missed {
+                    outputs.extend(self.run_group(input, image, &[size]));
+                }
+                outputs.push(save(image, target, Err(err)));
+            }
+        }
+
+        outputs
+    }
+}
+
+fn save_image<P: ColorPixel>(image: &ImageBufferVec<P>, path: &Path) -> CarveResult<()> {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir).writing(dir)?;
+    }
+    P::save(image, path)
+}
+
+/// The result of resizing a single image to a single size.
+#[derive(Debug)]
+pub struct BatchOutput {
+    pub input: PathBuf,
+    /// The size of the output, or `None` if the input couldn't be loaded.
+    pub size: Option<Vector2<i32>>,
+    pub output: Option<PathBuf>,
+    /// The time between the start of the output's resize and the output being saved. Sizes which
+    /// are produced by the same resize share their start.
+    pub elapsed: Duration,
+    pub result: CarveResult<()>,
+}
+
+/// The outputs of a [Batch], displayed as a summary table.
+#[derive(Debug)]
+pub struct BatchReport {
+    pub outputs: Vec<BatchOutput>,
+    pub elapsed: Duration,
+}
+
+impl BatchReport {
+    pub fn failures(&self) -> impl Iterator<Item = &BatchOutput> {
+        self.outputs.iter().filter(|output| output.result.is_err())
+    }
+}
+
+impl Display for BatchReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        let rows = self
+            .outputs
+            .iter()
+            .map(|output| {
+                [
+                    output.input.display().to_string(),
+                    output
+                        .size
+                        .map_or("-".to_string(), |size| format!("{}x{}", size.x, size.y)),
+                    output
+                        .output
+                        .as_ref()
+                        .map_or("-".to_string(), |path| path.display().to_string()),
+                    format!("{:.2?}", output.elapsed),
+                    match &output.result {
+                        Ok(_) => "ok".to_string(),
+                        Err(err) => err.to_string(),
+                    },
+                ]
+            })
+            .collect::<Vec<_>>();
+
+        let header = ["Input", "Size", "Output", "Time", "Status"].map(str::to_string);
+        let mut widths = [0; 5];
+        for row in std::iter::once(&header).chain(&rows) {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+
+        for row in std::iter::once(&header).chain(&rows) {
+            // The status column is last so it doesn't need to be padded.
+            for (i, (width, cell)) in widths.iter().zip(row).enumerate() {
+                if i + 1 < row.len() {
+                    write!(f, "{:width$}  ", cell, width = width)?;
+                } else {
+                    writeln!(f, "{}", cell)?;
+                }
+            }
+        }
+
+        let failed = self.failures().count();
+        write!(
+            f,
+            "\n{} output{} in {:.2?}, {} failed.",
+            self.outputs.len(),
+            if self.outputs.len() == 1 { "" } else { "s" },
+            self.elapsed,
+            failed
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inputs_and_outputs() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["cat.png", "cat.out.png", "at.png", "bat.jpg", "notes.txt"] {
+            fs::write(dir.path().join(name), []).unwrap();
+        }
+
+        let find = |pattern: &str| {
+            let names = find_inputs(&dir.path().join(pattern).to_string_lossy()).map(|inputs| {
+                inputs
+                    .iter()
+                    .map(|input| input.file_name().unwrap().to_string_lossy().into_owned())
+                    .collect::<Vec<_>>()
+            });
+            names.map_err(|err| err.to_string())
+        };
+        assert_eq!(find("*.png").unwrap(), ["at.png", "cat.out.png", "cat.png"]);
+        assert_eq!(
+            find("?at.*").unwrap(),
+            ["bat.jpg", "cat.out.png", "cat.png"]
+        );
+        assert_eq!(find("[bc]at.png").unwrap(), ["cat.png"]);
+        assert!(find("*.gif").is_err());
+        assert!(find("[.png").is_err());
+        assert_eq!(
+            find_inputs(&dir.path().to_string_lossy()).unwrap().len(),
+            4,
+            "directories expand to their images"
+        );
+
+        assert_eq!(
+            format_output(
+                DEFAULT_TEMPLATE,
+                Path::new("images/cat.png"),
+                Vector2::new(4, 3)
+            ),
+            PathBuf::from("cat_4x3.png")
+        );
+    }
+
+    #[test]
+    fn batches_match_single_resizes() {
+        let dir = tempfile::tempdir().unwrap();
+        let cat = image::open(format!("{}/images/cat.png", env!("CARGO_MANIFEST_DIR")))
+            .unwrap()
+            .into_rgba8();
+        let cat = image::imageops::thumbnail(&cat, 24, 20);
+        for name in ["a/cat.png", "b/cat.png"] {
+            fs::create_dir_all(dir.path().join(name).parent().unwrap()).unwrap();
+            cat.save(dir.path().join(name)).unwrap();
+        }
+        fs::write(dir.path().join("a/broken.png"), "not an image").unwrap();
+
+        let inputs = [
+            dir.path().join("a/cat.png"),
+            dir.path().join("a/broken.png"),
+            dir.path().join("b/cat.png"),
+        ];
+        let sizes = ["20xP", "16xP", "Px15", "18x18", "16x20"]
+            .map(|size| size.parse::<TargetSize>().unwrap());
+        assert!(
+            check_template(DEFAULT_TEMPLATE, &inputs, &sizes).is_err(),
+            "both cats would be saved to the same files"
+        );
+        let template = "{dir}/out/{stem}_{w}x{h}.{ext}";
+        check_template(template, &inputs, &sizes).unwrap();
+
+        let report = Batch {
+            inputs: &inputs,
+            sizes: &sizes,
+            template,
+            carver: Carver::new,
+        }
+        .run();
+
+        // The unreadable input fails on its own and the duplicate size is only produced once.
+        assert_eq!(report.outputs.len(), 9);
+        let failures = report.failures().collect::<Vec<_>>();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].input, inputs[1]);
+        assert_eq!(failures[0].size, None);
+
+        // Sizes saved while carving towards a smaller one match resizing to them directly.
+        for output in report.outputs.iter().filter(|output| output.result.is_ok()) {
+            let size = output.size.unwrap();
+            let expected = Carver::new().size(size).run(cat.clone()).unwrap().image;
+            let saved = image::open(output.output.as_ref().unwrap())
+                .unwrap()
+                .into_rgba8();
+            assert!(
+                saved == expected,
+                "{:?} differs from resizing to {}x{} directly",
+                output.output,
+                size.x,
+                size.y
+            );
+        }
+    }
+}
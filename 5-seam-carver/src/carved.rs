@@ -0,0 +1,302 @@
+use crate::carver::Axis;
+use crate::util::{Kernel, KernelRect, Timer};
+use cgmath::Vector2;
+
+/// A kernel which carves seams out of its backing kernel without copying it. Every line along the
+/// carved axis keeps a sorted list of the positions of the backing pixels which were removed from
+/// it and reads skip over them. The backing kernel is only compacted once every line has
+/// accumulated `threshold` removed pixels or when a seam is carved along the other axis.
+///
+/// Positions given to and produced by this kernel, including those of `encode_pos` and
+/// `decode_pos`, are always relative to the carved kernel.
+#[derive(Debug, Clone)]
+pub struct CarvedKernel<K> {
+    backing: K,
+    axis: Axis,
+    removed: Vec<Vec<i32>>,
+    threshold: usize,
+}
+
+impl<K: Kernel> CarvedKernel<K> {
+    /// The default number of seams carved between compactions. Reads get slower with every removed
+    /// pixel on their line so this shouldn't be too large.
+    pub const DEFAULT_THRESHOLD: usize = 16;
+
+    pub fn with_threshold(mut self, threshold: usize) -> Self {
+        self.threshold = threshold;
+        if self.removed_count() >= threshold {
+            self.compact();
+        }
+        self
+    }
+
+    /// The number of pixels which have been removed from every line since the last compaction.
+    pub fn removed_count(&self) -> usize {
+        self.removed.first().map_or(0, Vec::len)
+    }
+
+    /// The axis along which the lines of the kernel run. Seams carved along the other axis cause
+    /// the kernel to be compacted.
+    pub fn axis(&self) -> Axis {
+        self.axis
+    }
+
+    /// The removed pixels of the line at `along`.
+    fn removed_on(&self, along: i32) -> &[i32] {
+        self.removed.get(along as usize).map_or(&[], Vec::as_slice)
+    }
+
+    /// Maps a position in the carved kernel to its position in the backing kernel.
+    fn backing_pos(&self, pos: Vector2<i32>) -> Vector2<i32> {
+        let (along, across) = self.axis.split(pos);
+        self.axis
+            .pos(along, backing_across(self.removed_on(along), across))
+    }
+
+    /// Reads consecutive pixels of the line at `along` into `out`, starting at `start` across the
+    /// kernel's axis. This is much faster than reading every pixel individually.
+    pub fn read_line(&self, along: i32, start: i32, out: &mut [K::Pixel]) {
+        debug_assert!(start + out.len() as i32 <= self.axis.across(self.size()));
+        let cursor = LineCursor::new(self.axis, self.removed_on(along), along, start);
+        for (pixel, pos) in out.iter_mut().zip(cursor) {
+            *pixel = *self.backing.get(pos);
+        }
+    }
+
+    /// Overwrites consecutive pixels of the line at `along`, starting at `start` across the
+    /// kernel's axis.
+    pub fn write_line(&mut self, along: i32, start: i32, pixels: &[K::Pixel]) {
+        debug_assert!(start + pixels.len() as i32 <= self.axis.across(self.size()));
+        let removed = self
+            .removed
+            .get(along as usize)
+            .map_or(&[][..], Vec::as_slice);
+        let cursor = LineCursor::new(self.axis, removed, along, start);
+        for (pixel, pos) in pixels.iter().zip(cursor) {
+            *self.backing.get_mut(pos) = *pixel;
+        }
+    }
+
+    /// Carves a seam running along the specified axis out of the kernel. `seam` follows the same
+    /// conventions as [crate::carver::carve].
+    pub fn carve<I>(&mut self, axis: Axis, seam: I)
+    where
+        I: IntoIterator<Item = i32>,
+    {
+        let _timer = Timer::start("carve_lazy");
+
+        // Removals are only tracked along a single axis at a time.
+        if axis != self.axis {
+            self.compact();
+            self.axis = axis;
+        }
+
+        let size = self.size();
+        if self.removed.is_empty() {
+            self.removed = (0..axis.along(size))
+                .map(|_| Vec::with_capacity(self.threshold))
+                .collect();
+        }
+
+        let mut seam = seam.into_iter();
+        for along in (0..axis.along(size)).rev() {
+            let remove_at = seam.next().expect("`seam` has the wrong size!");
+            debug_assert!((0..axis.across(size)).contains(&remove_at));
+
+            let (_, backing) = axis.split(self.backing_pos(axis.pos(along, remove_at)));
+            let removed = &mut self.removed[along as usize];
+            let index = removed.partition_point(|removed| *removed < backing);
+            removed.insert(index, backing);
+        }
+
+        if self.removed_count() >= self.threshold {
+            self.compact();
+        }
+    }
+
+    /// Copies the remaining pixels into a new backing kernel, making reads fast again.
+    pub fn compact(&mut self) {
+        if self.removed_count() > 0 {
+            self.backing = self.compacted();
+            self.removed.clear();
+        }
+    }
+
+    /// Produces a regular kernel containing the remaining pixels.
+    pub fn compacted(&self) -> K {
+        if self.removed_count() == 0 {
+            return self.backing.clone();
+        }
+
+        let _timer = Timer::start("compact");
+
+        // Kernels are built in row-major order so every line is visited in increasing order across
+        // the axis. This lets us walk each line alongside it rather than searching for every pixel.
+        let axis = self.axis;
+        let mut cursors = (0..self.removed.len() as i32)
+            .map(|along| LineCursor::new(axis, self.removed_on(along), along, 0))
+            .collect::<Vec<_>>();
+
+        K::from_fn(self.size(), |pos| {
+            let (along, _) = axis.split(pos);
+            *self.backing.get(cursors[along as usize].next().unwrap())
+        })
+    }
+
+    pub fn into_inner(mut self) -> K {
+        self.compact();
+        self.backing
+    }
+}
+
+impl<K: Kernel> From<K> for CarvedKernel<K> {
+    fn from(backing: K) -> Self {
+        Self {
+            backing,
+            axis: Axis::Vertical,
+            removed: Vec::new(),
+            threshold: Self::DEFAULT_THRESHOLD,
+        }
+    }
+}
+
+impl<K: Kernel> Kernel for CarvedKernel<K> {
+    type Pixel = K::Pixel;
+
+    fn new(size: Vector2<i32>) -> Self {
+        Self::from(K::new(size))
+    }
+
+    fn from_fn<F>(size: Vector2<i32>, handler: F) -> Self
+    where
+        F: FnMut(Vector2<i32>) -> Self::Pixel,
+    {
+        Self::from(K::from_fn(size, handler))
+    }
+
+    fn get(&self, pos: Vector2<i32>) -> &Self::Pixel {
+        debug_assert!(self.contains_pos(pos));
+        self.backing.get(self.backing_pos(pos))
+    }
+
+    fn get_mut(&mut self, pos: Vector2<i32>) -> &mut Self::Pixel {
+        debug_assert!(self.contains_pos(pos));
+        let pos = self.backing_pos(pos);
+        self.backing.get_mut(pos)
+    }
+}
+
+impl<K: Kernel> KernelRect for CarvedKernel<K> {
+    fn size(&self) -> Vector2<i32> {
+        self.backing.size() - self.axis.unit() * self.removed_count() as i32
+    }
+}
+
+/// Maps a position across a line to its position in the backing kernel given the sorted positions
+/// of the line's removed pixels.
+fn backing_across(removed: &[i32], across: i32) -> i32 {
+    // The pixel is shifted over by every removed pixel which precedes it. `removed[i] - i` is the
+    // number of remaining pixels which precede the `i`th removed pixel so that pixel precedes the
+    // target pixel iff `removed[i] - i <= across`. This is monotonic in `i`, letting us binary
+    // search for the number of such pixels.
+    let (mut low, mut high) = (0, removed.len());
+    while low < high {
+        let mid = (low + high) / 2;
+        if removed[mid] - mid as i32 <= across {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+    across + low as i32
+}
+
+/// Walks the backing positions of consecutive pixels across a line.
+struct LineCursor<'a> {
+    axis: Axis,
+    removed: &'a [i32],
+    along: i32,
+    backing: i32,
+    next_removed: usize,
+}
+
+impl<'a> LineCursor<'a> {
+    fn new(axis: Axis, removed: &'a [i32], along: i32, start: i32) -> Self {
+        let backing = backing_across(removed, start);
+        Self {
+            axis,
+            removed,
+            along,
+            backing,
+            next_removed: removed.partition_point(|removed| *removed < backing),
+        }
+    }
+}
+
+impl Iterator for LineCursor<'_> {
+    type Item = Vector2<i32>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.removed.get(self.next_removed) == Some(&self.backing) {
+            self.backing += 1;
+            self.next_removed += 1;
+        }
+
+        let pos = self.axis.pos(self.along, self.backing);
+        self.backing += 1;
+        Some(pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::carver::carve;
+    use crate::util::VecKernel;
+
+    #[test]
+    fn lazy_carving_matches_copying_carving() {
+        let size = Vector2::new(37, 29);
+        let mut copied = VecKernel::<usize>::from_fn(size, |pos| size.encode_pos(pos));
+        let mut lazy = CarvedKernel::from(copied.clone()).with_threshold(5);
+
+        // A simple LCG is enough to pick arbitrary seams.
+        let mut state = 12345u32;
+        let mut random = |bound: i32| {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            (state >> 16) as i32 % bound
+        };
+
+        for i in 0..24 {
+            let axis = if i % 10 < 7 {
+                Axis::Vertical
+            } else {
+                Axis::Horizontal
+            };
+            let seam = (0..axis.along(copied.size()))
+                .map(|_| random(axis.across(copied.size())))
+                .collect::<Vec<_>>();
+
+            copied = carve(&copied, axis, seam.iter().copied());
+            lazy.carve(axis, seam.iter().copied());
+
+            assert_eq!(lazy.size(), copied.size());
+            for index in 0..copied.dim() {
+                let pos = copied.decode_pos(index);
+                assert_eq!(lazy.decode_pos(index), pos);
+                assert_eq!(lazy.get(pos), copied.get(pos));
+            }
+
+            // Lines must read the same pixels as individual reads.
+            let along = random(axis.along(lazy.size()));
+            let start = random(axis.across(lazy.size()));
+            let mut line = vec![0; (axis.across(lazy.size()) - start) as usize];
+            lazy.read_line(along, start, &mut line);
+            for (offset, pixel) in line.iter().enumerate() {
+                assert_eq!(pixel, lazy.get(axis.pos(along, start + offset as i32)));
+            }
+        }
+
+        assert_eq!(lazy.into_inner().pixels(), copied.pixels());
+    }
+}
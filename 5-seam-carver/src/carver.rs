@@ -1,29 +1,78 @@
-use crate::util::{Kernel, KernelRect, Timer, WeightImage};
-use cgmath::{InnerSpace, Vector2, Vector4, Zero};
-use image::{Luma, Rgba, RgbaImage};
+use crate::carved::CarvedKernel;
+use crate::depth::ColorPixel;
+use crate::energy::{
+    energy_from_name, ColorSpace, EnergyFilter, EnergyFunction, Filtered, InColorSpace, Saliency,
+    SeamDerivative,
+};
+use crate::error::{CarveError, CarveResult, PathContext};
+use crate::util::{
+    par_weight_image, ColorKernel, Kernel, KernelRect, Premultiplied, Timer, VecKernel, WeightImage,
+};
+use cgmath::{InnerSpace, Vector2, VectorSpace, Zero};
+use image::{DynamicImage, Luma, RgbaImage};
+use rayon::prelude::*;
 use std::cmp::Ordering;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
 
-/// Runs a simple horizontal sobel filter on the image.
-pub fn sobel(target: &RgbaImage) -> WeightImage {
-    fn rgba_to_vec4(pixel: &Rgba<u8>) -> Vector4<f32> {
-        Vector4::from(pixel.0).cast::<f32>().unwrap() / u8::MAX as f32
+/// The direction in which a seam runs through an image.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Axis {
+    /// Seams run from the top of the image to its bottom. Removing one shrinks the image's width.
+    Vertical,
+    /// Seams run from the left of the image to its right. Removing one shrinks the image's height.
+    Horizontal,
+}
+
+impl Axis {
+    /// Builds a position from a coordinate along the seam and a coordinate across it.
+    pub fn pos(self, along: i32, across: i32) -> Vector2<i32> {
+        match self {
+            Axis::Vertical => Vector2::new(across, along),
+            Axis::Horizontal => Vector2::new(along, across),
+        }
     }
 
-    let _timer = Timer::start("sobel");
-    target.map(|pos, _| {
-        let left = target.try_get(pos + Vector2::new(-1, 0));
-        let right = target.try_get(pos + Vector2::new(1, 0));
-        let luma = match (left, right) {
-            (Some(left), Some(right)) => {
-                let left = rgba_to_vec4(left);
-                let right = rgba_to_vec4(right);
+    /// Splits a position into its coordinate along the seam and its coordinate across it.
+    pub fn split(self, pos: Vector2<i32>) -> (i32, i32) {
+        match self {
+            Axis::Vertical => (pos.y, pos.x),
+            Axis::Horizontal => (pos.x, pos.y),
+        }
+    }
 
-                (right - left).magnitude()
-            }
-            _ => f32::MAX,
-        };
-        Luma([luma])
-    })
+    /// The length of a seam running along this axis.
+    pub fn along(self, size: Vector2<i32>) -> i32 {
+        match self {
+            Axis::Vertical => size.y,
+            Axis::Horizontal => size.x,
+        }
+    }
+
+    /// The number of seams which could be placed side-by-side along this axis.
+    pub fn across(self, size: Vector2<i32>) -> i32 {
+        match self {
+            Axis::Vertical => size.x,
+            Axis::Horizontal => size.y,
+        }
+    }
+
+    /// The amount by which an image shrinks when a seam along this axis is removed.
+    pub fn unit(self) -> Vector2<i32> {
+        self.pos(0, 1)
+    }
+}
+
+/// Runs a simple horizontal sobel filter on the image.
+pub fn sobel(target: &RgbaImage) -> WeightImage {
+    sobel_across(target, Axis::Vertical)
+}
+
+/// Runs a simple sobel filter on the image which only considers the derivative across seams of the
+/// specified axis.
+pub fn sobel_across(target: &RgbaImage, axis: Axis) -> WeightImage {
+    SeamDerivative.energy(target, axis)
 }
 
 /// Carve an image vertically across a seam.
@@ -32,19 +81,45 @@ where
     K: Kernel,
     I: IntoIterator<Item = i32>,
 {
-    let _timer = Timer::start("carve_vertical");
+    carve(target, Axis::Vertical, x_list)
+}
+
+/// Carve an image horizontally across a seam.
+pub fn carve_horizontal<K, I>(target: &K, y_list: I) -> K
+where
+    K: Kernel,
+    I: IntoIterator<Item = i32>,
+{
+    carve(target, Axis::Horizontal, y_list)
+}
+
+/// Carve an image across a seam running along the specified axis. `seam` yields the position of
+/// the pixel to remove for each step along the axis, starting from the end of the axis (i.e. the
+/// bottom row for vertical seams and the rightmost column for horizontal ones).
+pub fn carve<K, I>(target: &K, axis: Axis, seam: I) -> K
+where
+    K: Kernel,
+    I: IntoIterator<Item = i32>,
+{
+    let _timer = Timer::start(match axis {
+        Axis::Vertical => "carve_vertical",
+        Axis::Horizontal => "carve_horizontal",
+    });
     let target_sz = target.size();
-    let mut carved = K::new(target_sz - Vector2::new(1, 0));
-    let mut x_list = x_list.into_iter();
+    let mut carved = K::new(target_sz - axis.unit());
+    let mut seam = seam.into_iter();
 
-    for y in (0..target_sz.y).rev() {
-        let remove_at = x_list.next().expect("`x_list` has the wrong size!");
-        let mut write_x = 0;
-        for x in 0..target_sz.x {
+    for along in (0..axis.along(target_sz)).rev() {
+        let remove_at = seam.next().expect("`seam` has the wrong size!");
+        let mut write_at = 0;
+        for across in 0..axis.across(target_sz) {
             // Copy the pixel if we're not attempting to remove it.
-            if x != remove_at {
-                carved.put(Vector2::new(write_x, y), *target.get(Vector2::new(x, y)));
-                write_x += 1;
+            if across != remove_at {
+                carved.put(
+                    axis.pos(along, write_at),
+                    *target.get(axis.pos(along, across)),
+                );
+                write_at += 1;
             }
         }
     }
@@ -52,19 +127,889 @@ where
     carved
 }
 
+/// Marks the pixels of an image which belong to a seam with the index of that seam.
+pub type SeamMask = VecKernel<Option<i32>>;
+
+/// Finds the `count` lowest weighted seams running along `axis` by repeatedly carving a copy of the
+/// image. The seams are returned in the coordinate space of `image`. Because every seam is found
+/// on a carved image, no two seams ever share a pixel.
+pub fn find_seams<K>(
+    image: &K,
+    finder: &SeamFinder,
+    axis: Axis,
+    count: i32,
+    masks: &Masks,
+) -> SeamMask
+where
+    K: Kernel + Sync,
+    K::Pixel: ColorPixel,
+{
+    let _timer = Timer::start("find_seams");
+    let mut mask = SeamMask::new(image.size());
+    let mut map =
+        CarvedKernel::<VecKernel<usize>>::from_fn(image.size(), |pos| image.encode_pos(pos));
+    let mut image = CarvedKernel::from(image.clone());
+    let mut masks = masks.clone();
+    let mut cache = SeamCache::new(finder, &image, axis, &masks);
+
+    for i in 0..count {
+        let seam = cache.seam();
+
+        // Mark the seam in the original image's coordinate space.
+        let mut seam_iter = seam.iter();
+        for along in (0..axis.along(map.size())).rev() {
+            let across = seam_iter.next().unwrap();
+            let world_pos = mask.decode_pos(*map.get(axis.pos(along, across)));
+            mask.put(world_pos, Some(i));
+        }
+
+        // Remove it so the next seam doesn't pick it again.
+        map.carve(axis, seam.iter());
+        image.carve(axis, seam.iter());
+        masks.carve(seam);
+        if i + 1 < count {
+            cache.carve(finder, &image, &masks);
+        }
+    }
+
+    mask
+}
+
+/// Inserts a new seam next to every seam marked in `seams`. `split` is given the pixel preceding the
+/// seam pixel, the seam pixel itself, and the pixel following it (the seam pixel is substituted for
+/// its neighbors at the image's edges) and produces the two pixels which replace it.
+pub fn insert<K, F>(target: &K, axis: Axis, seams: &SeamMask, count: i32, mut split: F) -> K
+where
+    K: Kernel,
+    F: FnMut(&K::Pixel, &K::Pixel, &K::Pixel) -> [K::Pixel; 2],
+{
+    let _timer = Timer::start("insert");
+    let target_sz = target.size();
+    let mut inserted = K::new(target_sz + axis.unit() * count);
+
+    for along in 0..axis.along(target_sz) {
+        let mut write_at = 0;
+        for across in 0..axis.across(target_sz) {
+            let pos = axis.pos(along, across);
+            let curr = target.get(pos);
+
+            if seams.get(pos).is_some() {
+                let prev = target.try_get(pos - axis.unit()).unwrap_or(curr);
+                let next = target.try_get(pos + axis.unit()).unwrap_or(curr);
+                for pixel in split(prev, curr, next) {
+                    inserted.put(axis.pos(along, write_at), pixel);
+                    write_at += 1;
+                }
+            } else {
+                inserted.put(axis.pos(along, write_at), *curr);
+                write_at += 1;
+            }
+        }
+        debug_assert_eq!(
+            write_at,
+            axis.across(inserted.size()),
+            "`seams` must mark exactly `count` pixels along every line",
+        );
+    }
+
+    inserted
+}
+
+/// Inserts a new seam next to every seam marked in `seams`, averaging both copies of the seam with
+/// their neighbors to hide the duplication.
+pub fn insert_blended<K>(target: &K, axis: Axis, seams: &SeamMask, count: i32) -> K
+where
+    K: Kernel,
+    K::Pixel: ColorPixel,
+{
+    fn average<P: ColorPixel>(a: &P, b: &P) -> P {
+        let (a, b) = (a.to_vec4(), b.to_vec4());
+        if a.w == b.w {
+            return P::from_vec4(a.lerp(b, 0.5));
+        }
+
+        // Pixels of different opacity are averaged in premultiplied alpha so that the color of
+        // transparent pixels doesn't bleed into their neighbors.
+        let color = (a.truncate() * a.w + b.truncate() * b.w) / (a.w + b.w);
+        P::from_vec4(color.extend((a.w + b.w) / 2.))
+    }
+
+    insert(target, axis, seams, count, |prev, curr, next| {
+        [average(prev, curr), average(curr, next)]
+    })
+}
+
+/// The masks which adjust the weights of an image's pixels. These must be carved and inserted into
+/// alongside the image.
+#[derive(Debug, Clone, Default)]
+pub struct Masks {
+    pub protect: Option<CarvedKernel<WeightImage>>,
+    pub remove: Option<CarvedKernel<WeightImage>>,
+    pub guide: Option<SeamGuide>,
+}
+
+impl Masks {
+    /// Applies every mask to `weights`.
+    pub fn apply(&self, weights: &mut WeightImage) {
+        if self.protect.is_none() && self.remove.is_none() && self.guide.is_none() {
+            return;
+        }
+
+        for index in 0..weights.dim() {
+            let pos = weights.decode_pos(index);
+            let Luma([energy]) = *weights.get(pos);
+            weights.put(pos, Luma([self.weight_at(pos, energy)]));
+        }
+    }
+
+    /// Applies every mask to the `energy` of the pixel at `pos`. Protection adds
+    /// [PROTECTION_ENERGY] in proportion to the weight of the pixel, so that seams avoid it. Removal
+    /// masks are binary: every pixel with a non-zero weight gets the full negative
+    /// [REMOVAL_ENERGY] since a faint mark would otherwise let seams avoid it.
+    pub fn weight_at(&self, pos: Vector2<i32>, mut energy: f32) -> f32 {
+        if let Some(protect) = &self.protect {
+            energy += PROTECTION_ENERGY * protect.get(pos).0[0];
+        }
+
+        if let Some(guide) = &self.guide {
+            energy += guide.penalty(pos);
+        }
+
+        if let Some(remove) = &self.remove {
+            if remove.get(pos).0[0] > 0. {
+                energy = -REMOVAL_ENERGY;
+            }
+        }
+
+        energy
+    }
+
+    /// Carves `seam` out of every mask. The guide is left alone since it describes a single seam.
+    pub fn carve(&mut self, seam: &LowestDerivative) {
+        for mask in [&mut self.protect, &mut self.remove].into_iter().flatten() {
+            mask.carve(seam.axis(), seam.iter());
+        }
+    }
+
+    /// Inserts `seams` into every mask. The inserted pixels copy the weight of the seam pixel.
+    pub fn insert(&mut self, axis: Axis, seams: &SeamMask, count: i32) {
+        for mask in [&mut self.protect, &mut self.remove].into_iter().flatten() {
+            *mask = insert(mask, axis, seams, count, |_, curr, _| [*curr, *curr]);
+        }
+    }
+}
+
+/// Attracts seams towards a reference seam, e.g. the corresponding seam of the previous frame of a
+/// video, by penalizing pixels in proportion to their distance from it.
+#[derive(Debug, Clone)]
+pub struct SeamGuide {
+    pub axis: Axis,
+    /// The position across the axis of the reference seam on every line, in increasing order along
+    /// the axis.
+    pub seam: Vec<i32>,
+    /// The energy added per pixel of distance from the reference seam.
+    pub strength: f32,
+}
+
+impl SeamGuide {
+    /// The energy added to the pixel at `pos`.
+    pub fn penalty(&self, pos: Vector2<i32>) -> f32 {
+        let (along, across) = self.axis.split(pos);
+        match self.seam.get(along as usize) {
+            Some(seam) => self.strength * (across - seam).abs() as f32,
+            None => 0.,
+        }
+    }
+}
+
+/// The energy given to pixels marked for removal. This is large enough that any seam crossing a
+/// marked pixel is cheaper than every seam which doesn't.
+pub const REMOVAL_ENERGY: f32 = 1e6;
+
+/// Converts a mask image into per-pixel weights between `0` and `1`.
+pub fn load_mask(mask: &DynamicImage) -> WeightImage {
+    mask.to_luma8()
+        .map(|_, luma| Luma([luma.0[0] as f32 / u8::MAX as f32]))
+}
+
+/// Loads a mask image from a file. See [load_mask].
+pub fn load_mask_file(path: impl AsRef<Path>) -> CarveResult<WeightImage> {
+    let path = path.as_ref();
+    Ok(load_mask(&image::open(path).reading(path)?))
+}
+
+/// Checks whether any pixel of the mask is marked.
+pub fn mask_any<K>(mask: &K) -> bool
+where
+    K: Kernel<Pixel = Luma<f32>>,
+{
+    (0..mask.dim()).any(|index| mask.get(mask.decode_pos(index)).0[0] > 0.)
+}
+
+/// The energy added to fully protected pixels. This is large enough that crossing a protected pixel
+/// costs more than any seam through unprotected content but small enough that removal masks still
+/// take precedence.
+pub const PROTECTION_ENERGY: f32 = 1e5;
+
+/// The energy added to every pixel before it is scaled by its opacity when [SeamFinder::alpha] is
+/// enabled. This makes fully transparent pixels strictly cheaper than flat opaque ones so that the
+/// padding around sprites is carved out first.
+pub const OPACITY_ENERGY: f32 = 1e-3;
+
+/// The alpha from which pixels count as opaque content. See [opaque_content_mask].
+pub const OPAQUE_ALPHA: f32 = 0.5;
+
+/// Checks whether any pixel of the image is not fully opaque.
+pub fn has_transparency(image: &dyn ColorKernel) -> bool {
+    (0..image.dim()).any(|index| image.color(image.decode_pos(index)).w < 1.)
+}
+
+/// Marks every pixel which is at least [OPAQUE_ALPHA] opaque with a weight of `1`. Used as a
+/// protection mask, this makes every seam which crosses opaque content more expensive than every
+/// seam which doesn't.
+pub fn opaque_content_mask(image: &dyn ColorKernel) -> WeightImage {
+    par_weight_image(image.size(), |pos| {
+        if image.color(pos).w >= OPAQUE_ALPHA {
+            1.
+        } else {
+            0.
+        }
+    })
+}
+
+/// Determines the axis along which seams should be carved to remove every pixel marked in `mask` in
+/// as few passes as possible. Returns `None` if no pixel is marked.
+pub fn removal_axis<K>(mask: &K) -> Option<Axis>
+where
+    K: Kernel<Pixel = Luma<f32>>,
+{
+    let (min, max) = (0..mask.dim())
+        .map(|index| mask.decode_pos(index))
+        .filter(|pos| mask.get(*pos).0[0] > 0.)
+        .fold(None, |bounds: Option<(Vector2<i32>, Vector2<i32>)>, pos| {
+            Some(match bounds {
+                Some((min, max)) => (
+                    Vector2::new(pos.x.min(min.x), pos.y.min(min.y)),
+                    Vector2::new(pos.x.max(max.x), pos.y.max(max.y)),
+                ),
+                None => (pos, pos),
+            })
+        })?;
+
+    let bounds = max - min;
+    Some(if bounds.x <= bounds.y {
+        Axis::Vertical
+    } else {
+        Axis::Horizontal
+    })
+}
+
+/// Determines how the weight of a seam is computed.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum EnergyMode {
+    /// Weighs seams by the energy of the pixels they remove.
+    Backward,
+    /// Weighs seams by the energy of the new pixel adjacencies created by their removal, as
+    /// described by Rubinstein et al. in "Improved Seam Carving for Video Retargeting" (2008).
+    Forward,
+}
+
+/// The names accepted by [EnergyMode::from_name].
+pub const ENERGY_MODE_NAMES: &[&str] = &["backward", "forward"];
+
+impl EnergyMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "backward" => EnergyMode::Backward,
+            "forward" => EnergyMode::Forward,
+            _ => return None,
+        })
+    }
+}
+
+/// The shape of the seams searched for by [LowestDerivative].
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct SeamShape {
+    /// The number of pixels by which a seam may move across the axis from one line to the next.
+    /// Looser seams can follow diagonal structures rather than cutting through them. This may not
+    /// exceed [SeamShape::MAX_CONNECTIVITY].
+    pub connectivity: i32,
+    /// The number of pixels removed from every line by a seam. A wide seam is weighed by the sum of
+    /// the pixels it covers and is then carved out as `width` adjacent seams following the same
+    /// path, so every carve still removes a single pixel per line but only one in every `width`
+    /// carves has to find a seam.
+    pub width: i32,
+}
+
+impl SeamShape {
+    pub const MAX_CONNECTIVITY: i32 = i8::MAX as i32;
+
+    /// Checks that the seam is at least one pixel wide and may move by between `1` and
+    /// [SeamShape::MAX_CONNECTIVITY] pixels from one line to the next.
+    pub fn validate(self) -> CarveResult<()> {
+        if !(1..=Self::MAX_CONNECTIVITY).contains(&self.connectivity) {
+            return Err(CarveError::InvalidInput(format!(
+                "Seam connectivity must be between 1 and {}, not {}.",
+                Self::MAX_CONNECTIVITY,
+                self.connectivity
+            )));
+        }
+        if self.width < 1 {
+            return Err(CarveError::InvalidInput(format!(
+                "Seam width must be at least 1, not {}.",
+                self.width
+            )));
+        }
+        Ok(())
+    }
+
+    /// Narrows the seam so that it fits in a line of `across` pixels. The connectivity is clamped
+    /// to the range accepted by [SeamShape::validate], as seams store their moves in an `i8`.
+    pub fn fit(self, across: i32) -> Self {
+        Self {
+            connectivity: self.connectivity.clamp(1, Self::MAX_CONNECTIVITY),
+            width: self.width.min(across).max(1),
+        }
+    }
+}
+
+impl Default for SeamShape {
+    fn default() -> Self {
+        Self {
+            connectivity: 1,
+            width: 1,
+        }
+    }
+}
+
+/// The configuration used to weigh and find seams.
+#[derive(Debug, Clone)]
+pub struct SeamFinder {
+    pub mode: EnergyMode,
+    pub energy: Arc<dyn EnergyFunction>,
+    /// Whether [SeamCache] should only recompute the weights affected by the removal of a seam.
+    /// Disabling this produces the exact same seams, only slower.
+    pub incremental: bool,
+    pub shape: SeamShape,
+    /// Whether the transparency of the image is taken into account. The energy is computed from
+    /// premultiplied colors so that the color of transparent pixels doesn't matter, and the weight
+    /// of every pixel is scaled by its opacity (see [OPACITY_ENERGY]) so that seams go through
+    /// transparent pixels first.
+    pub alpha: bool,
+}
+
+impl Default for SeamFinder {
+    fn default() -> Self {
+        Self {
+            mode: EnergyMode::Backward,
+            energy: Arc::new(SeamDerivative),
+            incremental: true,
+            shape: SeamShape::default(),
+            alpha: false,
+        }
+    }
+}
+
+/// The energy functions which can be computed in a different [ColorSpace].
+const GRADIENT_ENERGY_NAMES: &[&str] = &["derivative", "sobel", "scharr", "l1"];
+
+/// A [SeamFinder] described by the names and parameters of its parts, as given on the command line
+/// or in job files. [FinderOptions::build] checks that the parts can be combined.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FinderOptions {
+    pub mode: EnergyMode,
+    /// One of [ENERGY_NAMES](crate::energy::ENERGY_NAMES).
+    pub energy: String,
+    /// The radius of the windowed energy functions, which fall back to their default when `None`.
+    pub radius: Option<i32>,
+    pub color: ColorSpace,
+    pub saliency: Option<Saliency>,
+    pub filters: Vec<EnergyFilter>,
+    pub shape: SeamShape,
+    pub incremental: bool,
+}
+
+impl Default for FinderOptions {
+    fn default() -> Self {
+        Self {
+            mode: EnergyMode::Backward,
+            energy: "derivative".to_string(),
+            radius: None,
+            color: ColorSpace::Rgba,
+            saliency: None,
+            filters: Vec::new(),
+            shape: SeamShape::default(),
+            incremental: true,
+        }
+    }
+}
+
+impl FinderOptions {
+    /// Composes the energy function and builds the finder. Color spaces, saliencies and filters
+    /// only apply to backward energy, and color spaces only to the gradient energy functions.
+    pub fn build(&self) -> CarveResult<SeamFinder> {
+        self.shape.validate()?;
+        if let Some(radius) = self.radius.filter(|radius| *radius < 0) {
+            return Err(CarveError::InvalidInput(format!(
+                "Energy radius must be non-negative, not {}.",
+                radius
+            )));
+        }
+        if let ColorSpace::Lab { lightness_weight } | ColorSpace::Oklab { lightness_weight } =
+            self.color
+        {
+            if lightness_weight.is_nan() || lightness_weight < 0. {
+                return Err(CarveError::InvalidInput(format!(
+                    "Lightness weight must be non-negative, not {}.",
+                    lightness_weight
+                )));
+            }
+        }
+
+        let backward_only = |arg: &str| {
+            Err(CarveError::InvalidInput(format!(
+                "`--{}` only applies to the `backward` energy mode.",
+                arg
+            )))
+        };
+
+        let mut energy = energy_from_name(&self.energy, self.radius).ok_or_else(|| {
+            CarveError::InvalidInput(format!("Unknown energy function `{}`.", self.energy))
+        })?;
+
+        if self.color != ColorSpace::Rgba {
+            if self.mode == EnergyMode::Forward {
+                return backward_only("energy-color");
+            }
+            if !GRADIENT_ENERGY_NAMES.contains(&self.energy.as_str()) {
+                return Err(CarveError::InvalidInput(format!(
+                    "`--energy-color` only applies to gradient energy functions, not `{}`.",
+                    self.energy
+                )));
+            }
+            energy = Arc::new(InColorSpace {
+                energy,
+                space: self.color,
+            });
+        }
+
+        if let Some(saliency) = &self.saliency {
+            if self.mode == EnergyMode::Forward {
+                return backward_only("saliency");
+            }
+            energy = Arc::new(saliency.blend(energy, self.radius));
+        }
+
+        if !self.filters.is_empty() {
+            if self.mode == EnergyMode::Forward {
+                return backward_only("energy-filter");
+            }
+            energy = Arc::new(Filtered {
+                energy,
+                filters: self.filters.clone(),
+            });
+        }
+
+        Ok(SeamFinder {
+            mode: self.mode,
+            energy,
+            incremental: self.incremental,
+            shape: self.shape,
+            alpha: false,
+        })
+    }
+}
+
+impl SeamFinder {
+    /// Computes the weight of every pixel in `image` for seams running along `axis`. Masks should be
+    /// applied to these weights before passing them to [SeamFinder::find].
+    pub fn weights(&self, image: &dyn ColorKernel, axis: Axis) -> WeightImage {
+        let mut weights = match self.mode {
+            EnergyMode::Backward => self.with_view(image, |view| self.energy.energy(view, axis)),
+            // Forward energy is derived from the image while finding the seam so pixels only carry
+            // the weights given to them by masks.
+            EnergyMode::Forward => Kernel::new(image.size()),
+        };
+
+        if self.alpha {
+            for (index, Luma([weight])) in weights.pixels_mut().enumerate() {
+                let alpha = image.color(image.decode_pos(index)).w;
+                *weight = (*weight + OPACITY_ENERGY) * alpha;
+            }
+        }
+        weights
+    }
+
+    /// Computes the weight of a single pixel. This is equivalent to reading the pixel from the
+    /// image produced by [SeamFinder::weights].
+    pub fn weight_at(&self, image: &dyn ColorKernel, axis: Axis, pos: Vector2<i32>) -> f32 {
+        let weight = match self.mode {
+            EnergyMode::Backward => {
+                self.with_view(image, |view| self.energy.energy_at(view, axis, pos))
+            }
+            EnergyMode::Forward => 0.,
+        };
+
+        if self.alpha {
+            (weight + OPACITY_ENERGY) * image.color(pos).w
+        } else {
+            weight
+        }
+    }
+
+    /// The distance up to which the pixels of the image can affect the weight of a seam passing
+    /// through a given pixel. `None` indicates that every pixel can affect every seam.
+    pub fn radius(&self) -> Option<i32> {
+        match self.mode {
+            EnergyMode::Backward => self.energy.radius(),
+            // Forward energy looks at the pixels neighboring the seam, including those of the line
+            // before it which are as far away as the seam can move.
+            EnergyMode::Forward => Some(self.shape.connectivity),
+        }
+    }
+
+    /// Finds the lowest weighted seam of `image` given the per-pixel `weights` produced by
+    /// [SeamFinder::weights].
+    pub fn find(
+        &self,
+        image: &dyn ColorKernel,
+        weights: WeightImage,
+        axis: Axis,
+    ) -> LowestDerivative {
+        let shape = self.shape.fit(axis.across(image.size()));
+        match self.mode {
+            EnergyMode::Backward => LowestDerivative::find_shaped(weights, axis, shape),
+            EnergyMode::Forward => self.with_view(image, |view| {
+                LowestDerivative::find_forward(view, weights, axis, shape)
+            }),
+        }
+    }
+
+    /// Updates `seam` after it has been carved out of the image. See [LowestDerivative::update].
+    pub fn update<W>(
+        &self,
+        seam: &mut LowestDerivative,
+        image: &dyn ColorKernel,
+        weights: W,
+        dirty: &[Range<i32>],
+    ) where
+        W: Fn(Vector2<i32>) -> f32 + Sync,
+    {
+        match self.mode {
+            EnergyMode::Backward => seam.update(weights, dirty),
+            EnergyMode::Forward => {
+                self.with_view(image, |view| seam.update_forward(view, weights, dirty))
+            }
+        }
+    }
+
+    /// Runs `handler` on the image as seen by the energy, i.e. premultiplied if [SeamFinder::alpha]
+    /// is enabled.
+    fn with_view<R>(
+        &self,
+        image: &dyn ColorKernel,
+        handler: impl FnOnce(&dyn ColorKernel) -> R,
+    ) -> R {
+        if self.alpha {
+            handler(&Premultiplied(image))
+        } else {
+            handler(image)
+        }
+    }
+}
+
+/// Keeps the energy and seam weights of an image up to date as seams are carved out of it. Rather
+/// than recomputing everything after every pass, only the energy of the pixels around the removed
+/// seam is recomputed and only the seam weights which actually change are cascaded again. Nothing
+/// is copied when carving thanks to [CarvedKernel].
+#[derive(Debug, Clone)]
+pub struct SeamCache {
+    energy: CarvedKernel<WeightImage>,
+    seam: LowestDerivative,
+    /// The number of carves left before every pixel of a wide seam has been removed.
+    remaining: i32,
+}
+
+impl SeamCache {
+    pub fn new(finder: &SeamFinder, image: &dyn ColorKernel, axis: Axis, masks: &Masks) -> Self {
+        let energy = finder.weights(image, axis);
+        let mut weights = energy.clone();
+        masks.apply(&mut weights);
+
+        let seam = finder.find(image, weights, axis);
+        Self {
+            remaining: seam.shape.width - 1,
+            seam,
+            energy: CarvedKernel::from(energy),
+        }
+    }
+
+    pub fn axis(&self) -> Axis {
+        self.seam.axis()
+    }
+
+    /// The per-pixel weights of the image before masks are applied.
+    pub fn energy(&self) -> &CarvedKernel<WeightImage> {
+        &self.energy
+    }
+
+    /// The lowest weighted seam of the image.
+    pub fn seam(&self) -> &LowestDerivative {
+        &self.seam
+    }
+
+    /// Updates the cache once [SeamCache::seam] has been carved out of `image` and `masks`.
+    pub fn carve(&mut self, finder: &SeamFinder, image: &dyn ColorKernel, masks: &Masks) {
+        let _timer = Timer::start("SeamCache::carve");
+        let axis = self.axis();
+
+        // The rest of a wide seam follows the same path so nothing needs to be recomputed until
+        // all of it has been removed. Its pixels then collapse onto that same path.
+        if self.remaining > 0 {
+            self.remaining -= 1;
+            self.energy.carve(axis, self.seam.iter());
+            self.seam.carve_path();
+            return;
+        }
+
+        let shape = self.seam.shape;
+        let radius = match finder.radius() {
+            Some(radius)
+                if finder.incremental && finder.shape.fit(axis.across(image.size())) == shape =>
+            {
+                radius.max(1)
+            }
+            _ => {
+                *self = Self::new(finder, image, axis, masks);
+                return;
+            }
+        };
+
+        // A pixel's neighborhood only changes if it lies within `radius` of the seam on its own
+        // line or, because seams move by at most `connectivity` pixels per line, within
+        // `(connectivity + 1) * radius` of it. The weight of a wide seam also covers the pixels
+        // which follow it.
+        let reach = (shape.connectivity + 1) * radius;
+        let mut dirty = self
+            .seam
+            .iter()
+            .map(|across| across - reach - shape.width..across + reach + 1)
+            .collect::<Vec<_>>();
+        dirty.reverse();
+
+        // Recompute the energy of the dirty pixels, one line per task.
+        self.energy.carve(axis, self.seam.iter());
+        let across_size = axis.across(image.size());
+        let lines = dirty
+            .par_iter()
+            .enumerate()
+            .map(|(along, dirty)| {
+                let start = dirty.start.max(0);
+                let line = (start..dirty.end.min(across_size))
+                    .map(|across| {
+                        let pos = axis.pos(along as i32, across);
+                        Luma([finder.weight_at(image, axis, pos)])
+                    })
+                    .collect::<Vec<_>>();
+                (start, line)
+            })
+            .collect::<Vec<_>>();
+
+        for (along, (start, line)) in lines.iter().enumerate() {
+            self.energy.write_line(along as i32, *start, line);
+        }
+
+        // The guide moves with every seam so every weight changes and the seam must be found from
+        // scratch.
+        if masks.guide.is_some() {
+            let mut weights = self.energy.compacted();
+            masks.apply(&mut weights);
+            self.seam = finder.find(image, weights, axis);
+            self.remaining = self.seam.shape.width - 1;
+            return;
+        }
+
+        // Update the seam weights
+        let energy = &self.energy;
+        let weights = |pos| masks.weight_at(pos, energy.get(pos).0[0]);
+        finder.update(&mut self.seam, image, weights, &dirty);
+        self.remaining = shape.width - 1;
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct LowestDerivative {
-    target: WeightImage,
-    best_x: i32,
+    target: CarvedKernel<WeightImage>,
+    choices: CarvedKernel<VecKernel<i8>>,
+    axis: Axis,
+    shape: SeamShape,
     best_weight: f32,
+    /// The position of the seam across every line, from the last line to the first.
+    path: Vec<i32>,
 }
 
 fn cmp_second_weight<T>((_, weight_a): &(T, f32), (_, weight_b): &(T, f32)) -> Ordering {
     weight_a.partial_cmp(weight_b).unwrap()
 }
 
+/// The minimum number of pixels of a line which are cascaded by a single task. Every line only
+/// depends on the line before it so its pixels can be cascaded in parallel, but splitting short
+/// lines isn't worth the overhead.
+pub const CASCADE_CHUNK: usize = 256;
+
+/// Computes the cumulative weight of the pixel at `pos` given its own `weight` and the cumulative
+/// weights of the previous line, returning the offset of the pixel it is best reached from. `prev`
+/// produces the cumulative weight of the pixel at a given position across the previous line, if
+/// it exists.
+pub fn cascade_cell<P, F>(
+    prev: P,
+    axis: Axis,
+    connectivity: i32,
+    pos: Vector2<i32>,
+    weight: f32,
+    transition: &F,
+) -> (i8, f32)
+where
+    P: Fn(i32) -> Option<f32>,
+    F: Fn(Vector2<i32>, i32) -> f32,
+{
+    let (_, across) = axis.split(pos);
+    let (rel, prev_weight) = (-connectivity..=connectivity)
+        .filter_map(|rel| Some((rel, prev(across + rel)? + transition(pos, rel))))
+        .min_by(cmp_second_weight)
+        .unwrap_or((0, 0.));
+
+    (rel as i8, weight + prev_weight)
+}
+
+/// Produces the forward energy cost of reaching the pixel at `pos` from the pixel on the previous
+/// line offset by `rel` across the axis. `width` is the number of pixels removed from every line.
+fn forward_transition(
+    image: &dyn ColorKernel,
+    axis: Axis,
+    width: i32,
+) -> impl Fn(Vector2<i32>, i32) -> f32 + '_ {
+    // Reads a pixel, substituting the nearest pixel on the same line at the image's edges.
+    let size = image.size();
+    let pixel = move |along: i32, across: i32| {
+        let across = across.clamp(0, axis.across(size) - 1);
+        image.color(axis.pos(along, across))
+    };
+
+    move |pos, rel| {
+        let (along, across) = axis.split(pos);
+
+        // Removing the pixels joins their two neighbors on the same line...
+        let cost_up = (pixel(along, across + width) - pixel(along, across - 1)).magnitude();
+
+        // ...and, if the seam moves diagonally, joins every pixel of the previous line which gets
+        // shifted over the gap with the pixel which ends up underneath it.
+        let cost_diagonal: f32 = if rel < 0 {
+            (across + rel..across)
+                .map(|x| (pixel(along - 1, x + width) - pixel(along, x)).magnitude())
+                .sum()
+        } else {
+            (across..across + rel)
+                .map(|x| (pixel(along - 1, x) - pixel(along, x + width)).magnitude())
+                .sum()
+        };
+        cost_up + cost_diagonal
+    }
+}
+
+/// Sums up the weights of the `width` pixels starting at every pixel, which is the weight of a seam
+/// of that width passing through it. Seams can't start at pixels less than `width` pixels from the
+/// end of their line so those are given an infinite weight.
+pub fn window_weight<W>(
+    weights: W,
+    axis: Axis,
+    width: i32,
+    across_size: i32,
+    pos: Vector2<i32>,
+) -> f32
+where
+    W: Fn(Vector2<i32>) -> f32,
+{
+    let (along, across) = axis.split(pos);
+    if across + width > across_size {
+        return f32::INFINITY;
+    }
+    (1..width).fold(weights(pos), |sum, offset| {
+        sum + weights(axis.pos(along, across + offset))
+    })
+}
+
 impl LowestDerivative {
-    pub fn find(mut target: WeightImage) -> LowestDerivative {
+    /// Finds the lowest weighted vertical seam.
+    pub fn find(target: WeightImage) -> LowestDerivative {
+        Self::find_along(target, Axis::Vertical)
+    }
+
+    /// Finds the lowest weighted horizontal seam.
+    pub fn find_horizontal(target: WeightImage) -> LowestDerivative {
+        Self::find_along(target, Axis::Horizontal)
+    }
+
+    /// Finds the lowest weighted seam running along the specified axis.
+    pub fn find_along(target: WeightImage, axis: Axis) -> LowestDerivative {
+        Self::find_shaped(target, axis, SeamShape::default())
+    }
+
+    /// Finds the lowest weighted seam of the given shape running along the specified axis.
+    pub fn find_shaped(target: WeightImage, axis: Axis, shape: SeamShape) -> LowestDerivative {
+        Self::find_with(target, axis, shape, |_, _| 0.)
+    }
+
+    /// Finds the seam running along the specified axis whose removal inserts the least energy into
+    /// `image`. `target` gives every pixel an additional weight (e.g. from masks) and can be left
+    /// zeroed otherwise.
+    pub fn find_forward(
+        image: &dyn ColorKernel,
+        target: WeightImage,
+        axis: Axis,
+        shape: SeamShape,
+    ) -> LowestDerivative {
+        debug_assert_eq!(image.size(), target.size());
+        Self::find_with(
+            target,
+            axis,
+            shape,
+            forward_transition(image, axis, shape.width),
+        )
+    }
+
+    /// Carves this seam out of the image and updates the seams accordingly. `weights` produces the
+    /// per-pixel weights of the carved image, which must only differ from the carved weights of the
+    /// original image within the `dirty` range of every line along the axis. Only the seam weights
+    /// which are affected by those changes are recomputed.
+    pub fn update<W>(&mut self, weights: W, dirty: &[Range<i32>])
+    where
+        W: Fn(Vector2<i32>) -> f32 + Sync,
+    {
+        self.update_with(weights, dirty, |_, _| 0.)
+    }
+
+    /// The forward energy counterpart of [LowestDerivative::update]. Pixels next to the removed seam
+    /// must be included in `dirty` because their forward energy depends on their new neighbors.
+    pub fn update_forward<W>(&mut self, image: &dyn ColorKernel, weights: W, dirty: &[Range<i32>])
+    where
+        W: Fn(Vector2<i32>) -> f32 + Sync,
+    {
+        let transition = forward_transition(image, self.axis, self.shape.width);
+        self.update_with(weights, dirty, transition)
+    }
+
+    /// Runs the seam dynamic programming pass. `transition` produces the cost of reaching the pixel
+    /// at `pos` from the pixel on the previous line offset by `rel` across the axis.
+    fn find_with<F>(
+        mut target: WeightImage,
+        axis: Axis,
+        shape: SeamShape,
+        transition: F,
+    ) -> LowestDerivative
+    where
+        F: Fn(Vector2<i32>, i32) -> f32 + Sync,
+    {
         let _timer = Timer::start("LowestDerivative::find");
 
         // Fetch and validate image dimensions
@@ -74,88 +1019,926 @@ impl LowestDerivative {
             "Image dimensions must be non-zero (got {:?})",
             size,
         );
+        debug_assert!(shape.width <= axis.across(size));
+
+        if shape.width > 1 {
+            let weights = |pos| target.get(pos).0[0];
+            target = par_weight_image(size, |pos| {
+                window_weight(weights, axis, shape.width, axis.across(size), pos)
+            });
+        }
 
         // Cascade minimum seam weights
-        for y in 0..size.y {
-            for x in 0..size.x {
-                let pos = Vector2::new(x, y);
-                let weight = target.get(pos).0[0]
-                    + [
-                        target.try_get(pos + Vector2::new(-1, -1)),
-                        target.try_get(pos + Vector2::new(0, -1)),
-                        target.try_get(pos + Vector2::new(1, -1)),
-                    ]
-                    .into_iter()
-                    .filter_map(|luma| {
-                        if let Some(Luma([luma])) = luma {
-                            Some(*luma)
-                        } else {
-                            None
-                        }
-                    })
-                    .min_by(|a, b| a.partial_cmp(&b).unwrap())
-                    .unwrap_or(0.);
+        let mut choices = VecKernel::<i8>::new(size);
+        let mut line = Vec::new();
+        for along in 0..axis.along(size) {
+            let target_ref = &target;
+            let prev = |across| Some(target_ref.try_get(axis.pos(along - 1, across))?.0[0]);
+            (0..axis.across(size))
+                .into_par_iter()
+                .with_min_len(CASCADE_CHUNK)
+                .map(|across| {
+                    let pos = axis.pos(along, across);
+                    let weight = target_ref.get(pos).0[0];
+                    cascade_cell(prev, axis, shape.connectivity, pos, weight, &transition)
+                })
+                .collect_into_vec(&mut line);
 
-                *target.get_mut(pos) = Luma([weight]);
+            for (across, (rel, weight)) in line.iter().enumerate() {
+                let pos = axis.pos(along, across as i32);
+                *target.get_mut(pos) = Luma([*weight]);
+                choices.put(pos, *rel);
             }
         }
 
-        // Find the lowest base weight.
-        let (best_x, best_weight) = (0..size.x)
-            .map(|x| (x, target.get_pixel(x as u32, (size.y - 1) as u32).0[0]))
-            .min_by(cmp_second_weight)
-            .unwrap();
+        Self::from_cascade(target, choices, axis, shape)
+    }
+
+    fn update_with<W, F>(&mut self, weights: W, dirty: &[Range<i32>], transition: F)
+    where
+        W: Fn(Vector2<i32>) -> f32 + Sync,
+        F: Fn(Vector2<i32>, i32) -> f32 + Sync,
+    {
+        let _timer = Timer::start("LowestDerivative::update");
+        let axis = self.axis;
+        let SeamShape {
+            connectivity,
+            width,
+        } = self.shape;
+        self.carve_path();
+
+        let target = &mut self.target;
+        let choices = &mut self.choices;
+        let size = target.size();
+        let across_size = axis.across(size);
+        debug_assert_eq!(target.axis(), axis);
+        debug_assert_eq!(dirty.len(), axis.along(size) as usize);
+
+        // Reading the carved kernels one pixel at a time is slow so we copy the affected portions of
+        // every line into these buffers.
+        let mut prev_line = Vec::new();
+        let mut line = Vec::new();
+        let mut line_choices = Vec::new();
+        let mut cascaded = Vec::new();
+
+        // Cascade the seam weights of the dirty pixels. A pixel must also be recomputed if one of
+        // the pixels it could be reached from changed weight, so changes spread out by
+        // `connectivity` pixels every line.
+        let mut changed: Option<Range<i32>> = None;
+        for (along, dirty) in (0..axis.along(size)).zip(dirty) {
+            let (start, end) = match changed.take() {
+                Some(changed) => (
+                    dirty.start.min(changed.start - connectivity),
+                    dirty.end.max(changed.end + connectivity),
+                ),
+                None => (dirty.start, dirty.end),
+            };
+            let (start, end) = (start.max(0), end.min(across_size));
+            if start >= end {
+                continue;
+            }
+
+            // Fetch the pixels of the previous line which the dirty pixels can be reached from.
+            let prev_start = (start - connectivity).max(0);
+            let prev_end = if along > 0 {
+                (end + connectivity).min(across_size)
+            } else {
+                prev_start
+            };
+            prev_line.resize((prev_end - prev_start) as usize, Luma([0.]));
+            if along > 0 {
+                target.read_line(along - 1, prev_start, &mut prev_line);
+            }
+            let prev_line = &prev_line;
+            let prev = |across: i32| {
+                let index = usize::try_from(across - prev_start).ok()?;
+                Some(prev_line.get(index)?.0[0])
+            };
+
+            (start..end)
+                .into_par_iter()
+                .with_min_len(CASCADE_CHUNK)
+                .map(|across| {
+                    let pos = axis.pos(along, across);
+                    let weight = window_weight(&weights, axis, width, across_size, pos);
+                    cascade_cell(prev, axis, connectivity, pos, weight, &transition)
+                })
+                .collect_into_vec(&mut cascaded);
+
+            line.resize((end - start) as usize, Luma([0.]));
+            line_choices.resize((end - start) as usize, 0);
+            target.read_line(along, start, &mut line);
+
+            for (index, (rel, weight)) in cascaded.iter().copied().enumerate() {
+                let across = start + index as i32;
+
+                if weight.to_bits() != line[index].0[0].to_bits() {
+                    changed = Some(match changed {
+                        Some(changed) => changed.start..across + 1,
+                        None => across..across + 1,
+                    });
+                }
+
+                line[index] = Luma([weight]);
+                line_choices[index] = rel;
+            }
+
+            target.write_line(along, start, &line);
+            choices.write_line(along, start, &line_choices);
+        }
+
+        let (best_across, best_weight) = Self::find_best(target, axis);
+        self.best_weight = best_weight;
+        self.path = Self::trace(choices, axis, best_across);
+    }
+
+    fn from_cascade(
+        target: WeightImage,
+        choices: VecKernel<i8>,
+        axis: Axis,
+        shape: SeamShape,
+    ) -> LowestDerivative {
+        let (best_across, best_weight) = Self::find_best(&target, axis);
+        let choices = CarvedKernel::from(choices);
 
         Self {
-            target,
-            best_x,
+            target: CarvedKernel::from(target),
+            path: Self::trace(&choices, axis, best_across),
+            choices,
+            axis,
+            shape,
             best_weight,
         }
     }
 
+    /// Follows the path chosen while cascading the seam weights from `best_across` on the last
+    /// line back to the first line.
+    fn trace(choices: &CarvedKernel<VecKernel<i8>>, axis: Axis, best_across: i32) -> Vec<i32> {
+        let mut across = best_across;
+        (0..axis.along(choices.size()))
+            .rev()
+            .map(|along| {
+                let curr = across;
+                across += *choices.get(axis.pos(along, curr)) as i32;
+                curr
+            })
+            .collect()
+    }
+
+    /// Carves the seam's path out of its own weights without updating them. This is used to remove
+    /// the pixels of a wide seam which follow the first one.
+    fn carve_path(&mut self) {
+        self.target.carve(self.axis, self.path.iter().copied());
+        self.choices.carve(self.axis, self.path.iter().copied());
+    }
+
+    /// Finds the lowest base weight.
+    fn find_best<K>(target: &K, axis: Axis) -> (i32, f32)
+    where
+        K: Kernel<Pixel = Luma<f32>>,
+    {
+        let size = target.size();
+        let last = axis.along(size) - 1;
+        (0..axis.across(size))
+            .map(|across| (across, target.get(axis.pos(last, across)).0[0]))
+            .min_by(cmp_second_weight)
+            .unwrap()
+    }
+
+    pub fn axis(&self) -> Axis {
+        self.axis
+    }
+
+    pub fn shape(&self) -> SeamShape {
+        self.shape
+    }
+
     pub fn weight(&self) -> f32 {
         self.best_weight
     }
 
-    pub fn weights(&self) -> &WeightImage {
+    pub fn weights(&self) -> &CarvedKernel<WeightImage> {
         &self.target
     }
 
+    /// The positions of the seam's pixels, from the last line to the first.
+    pub fn positions(&self) -> impl Iterator<Item = Vector2<i32>> + '_ {
+        let axis = self.axis;
+        (0..axis.along(self.target.size()))
+            .rev()
+            .zip(self.iter())
+            .map(move |(along, across)| axis.pos(along, across))
+    }
+
     pub fn iter(&self) -> LowestDerivativeSeam<'_> {
         LowestDerivativeSeam {
-            target: self,
-            iter_pos: Some(Vector2::new(self.best_x, (self.target.height() - 1) as i32)),
+            path: self.path.iter(),
         }
     }
 }
 
+/// The position across every line of a [LowestDerivative], from the last line to the first. Wide
+/// seams yield the first pixel they cover on every line.
 #[derive(Debug, Clone)]
 pub struct LowestDerivativeSeam<'a> {
-    target: &'a LowestDerivative,
-    iter_pos: Option<Vector2<i32>>,
+    path: std::slice::Iter<'a, i32>,
 }
 
 impl Iterator for LowestDerivativeSeam<'_> {
     type Item = i32;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let curr_iter_pos = self.iter_pos?;
-
-        // Find the best path in the neighboring area.
-        let next_pos = [
-            Vector2::new(-1, -1),
-            Vector2::new(0, -1),
-            Vector2::new(1, -1),
-        ]
-        .iter()
-        .filter_map(|rel| {
-            let rel_pos = curr_iter_pos + rel;
-            Some((rel_pos, self.target.target.try_get(rel_pos)?.0[0]))
+        self.path.next().copied()
+    }
+}
+
+/// The order in which vertical and horizontal seams are removed when both of the image's dimensions
+/// shrink.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum SeamOrder {
+    /// Removes every vertical seam before removing any horizontal seam.
+    WidthFirst,
+    /// Alternates between vertical and horizontal seams until one axis runs out.
+    Alternating,
+    /// Picks the interleaving with the lowest total seam weight using a transport map.
+    Optimal,
+}
+
+/// The names accepted by [SeamOrder::from_name].
+pub const SEAM_ORDER_NAMES: &[&str] = &["width-first", "alternating", "optimal"];
+
+impl SeamOrder {
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "width-first" => SeamOrder::WidthFirst,
+            "alternating" => SeamOrder::Alternating,
+            "optimal" => SeamOrder::Optimal,
+            _ => return None,
         })
-        .min_by(cmp_second_weight)
-        .map(|(next_pos, _)| next_pos);
+    }
+
+    /// Determines the axis of every seam which must be removed to shrink `image` by `remove`.
+    /// `masks` must have the same size as `image`.
+    pub fn plan<K>(
+        self,
+        image: &K,
+        finder: &SeamFinder,
+        masks: &Masks,
+        remove: Vector2<i32>,
+    ) -> Vec<Axis>
+    where
+        K: Kernel + Sync,
+        K::Pixel: ColorPixel,
+    {
+        let cols = std::iter::repeat(Axis::Vertical).take(remove.x as usize);
+        let rows = std::iter::repeat(Axis::Horizontal).take(remove.y as usize);
+
+        match self {
+            SeamOrder::WidthFirst => cols.chain(rows).collect(),
+            SeamOrder::Alternating => {
+                let mut cols = cols.peekable();
+                let mut rows = rows.peekable();
+                let mut steps = Vec::with_capacity((remove.x + remove.y) as usize);
+                while cols.peek().is_some() || rows.peek().is_some() {
+                    steps.extend(cols.next());
+                    steps.extend(rows.next());
+                }
+                steps
+            }
+            SeamOrder::Optimal => transport_order(image, finder, masks, remove),
+        }
+    }
+}
+
+/// Finds the cheapest interleaving of vertical and horizontal seam removals by running dynamic
+/// programming over the number of rows and columns removed (Avidan & Shamir's "transport map").
+///
+/// Every one of the `(remove.x + 1) * (remove.y + 1)` intermediate sizes is carved out and up to
+/// two seams are found from scratch for it, one per axis, so this does roughly
+/// `2 * (remove.x + 1) * (remove.y + 1)` full energy and seam passes where carving the image only
+/// does `remove.x + remove.y` incremental ones. One row of intermediate images, i.e.
+/// `remove.x + 1` copies of the image and its masks, is kept in memory at any given time.
+pub fn transport_order<K>(
+    image: &K,
+    finder: &SeamFinder,
+    masks: &Masks,
+    remove: Vector2<i32>,
+) -> Vec<Axis>
+where
+    K: Kernel + Sync,
+    K::Pixel: ColorPixel,
+{
+    let _timer = Timer::start("transport_order");
+
+    // `choices` records the axis of the last seam removed to reach each `(cols, rows)` state.
+    let mut choices = VecKernel::<Option<Axis>>::new(remove + Vector2::new(1, 1));
+
+    // The cost, image and masks of every state of the row being computed. States are replaced in
+    // place: while computing `(cols, rows)`, `row[cols]` still holds the state above it while
+    // `row[cols - 1]` already holds the state to its left.
+    let mut row: Vec<(f32, K, Masks)> = Vec::with_capacity(remove.x as usize + 1);
+
+    for rows in 0..=remove.y {
+        for cols in 0..=remove.x {
+            let from_above = (rows > 0).then(|| (Axis::Horizontal, &row[cols as usize]));
+            let from_left = (cols > 0).then(|| (Axis::Vertical, &row[cols as usize - 1]));
+
+            let best = from_above
+                .into_iter()
+                .chain(from_left)
+                .map(|(axis, (cost, image, masks))| {
+                    let mut weights = finder.weights(image, axis);
+                    masks.apply(&mut weights);
+                    let seam = finder.find(image, weights, axis);
+                    (cost + seam.weight(), image, masks, seam)
+                })
+                .min_by(|(a, ..), (b, ..)| a.partial_cmp(b).unwrap());
+
+            let state = match best {
+                Some((cost, image, masks, seam)) => {
+                    choices.put(Vector2::new(cols, rows), Some(seam.axis()));
+                    let mut masks = masks.clone();
+                    masks.carve(&seam);
+                    (cost, carve(image, seam.axis(), seam.iter()), masks)
+                }
+                None => (0., image.clone(), masks.clone()),
+            };
+
+            match row.get_mut(cols as usize) {
+                Some(slot) => *slot = state,
+                None => row.push(state),
+            }
+        }
+    }
+
+    // Backtrack from the target size to the original size.
+    let mut steps = Vec::with_capacity((remove.x + remove.y) as usize);
+    let mut pos = remove;
+    while let Some(axis) = *choices.get(pos) {
+        steps.push(axis);
+        pos -= axis.unit();
+    }
+    steps.reverse();
+    steps
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resize::Carver;
+    use crate::util::{rgba_to_vec4, srgb_to_lab};
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+
+    fn open_image(name: &str) -> RgbaImage {
+        let path = format!("{}/images/{}", env!("CARGO_MANIFEST_DIR"), name);
+        image::open(path).unwrap().into_rgba8()
+    }
+
+    /// Measures the energy of the new pixel adjacencies created by removing a vertical seam, which
+    /// is the quantity forward energy minimizes.
+    fn inserted_energy(image: &RgbaImage, seam: &LowestDerivative) -> f32 {
+        let mut xs = seam.iter().collect::<Vec<_>>();
+        xs.reverse();
+
+        let width = image.width() as i32;
+        let pixel =
+            |x: i32, y: i32| rgba_to_vec4(image.get(Vector2::new(x.clamp(0, width - 1), y)));
+
+        let mut energy = 0.;
+        for (y, x) in xs.iter().copied().enumerate() {
+            let y = y as i32;
+            energy += (pixel(x + 1, y) - pixel(x - 1, y)).magnitude();
+            if y > 0 {
+                energy += match xs[y as usize - 1] - x {
+                    -1 => (pixel(x, y - 1) - pixel(x - 1, y)).magnitude(),
+                    1 => (pixel(x, y - 1) - pixel(x + 1, y)).magnitude(),
+                    _ => 0.,
+                };
+            }
+        }
+        energy
+    }
+
+    #[test]
+    fn forward_energy_inserts_less_energy_than_backward_energy() {
+        let image = open_image("castle.jpg");
+        let backward = SeamFinder {
+            mode: EnergyMode::Backward,
+            ..Default::default()
+        };
+        let forward = SeamFinder {
+            mode: EnergyMode::Forward,
+            ..Default::default()
+        };
+
+        let backward_seam = backward.find(
+            &image,
+            backward.weights(&image, Axis::Vertical),
+            Axis::Vertical,
+        );
+        let forward_seam = forward.find(
+            &image,
+            forward.weights(&image, Axis::Vertical),
+            Axis::Vertical,
+        );
+
+        let backward_inserted = inserted_energy(&image, &backward_seam);
+        let forward_inserted = inserted_energy(&image, &forward_seam);
+
+        // The forward seam's weight is exactly the energy it inserts...
+        assert!((forward_seam.weight() - forward_inserted).abs() <= forward_inserted * 1e-3);
+
+        // ...and it is the seam which inserts the least energy.
+        assert!(
+            forward_inserted <= backward_inserted,
+            "forward seam inserted {} but backward seam inserted {}",
+            forward_inserted,
+            backward_inserted,
+        );
+    }
+
+    #[test]
+    fn incremental_updates_match_full_recomputation() {
+        let image = open_image("cat.png");
+        let image = image::imageops::thumbnail(&image, 80, 60);
+
+        for energy in ["derivative", "sobel", "entropy", "hog"] {
+            for mode in [EnergyMode::Backward, EnergyMode::Forward] {
+                let finder = SeamFinder {
+                    mode,
+                    energy: crate::energy::energy_from_name(energy, None).unwrap(),
+                    incremental: true,
+                    shape: SeamShape::default(),
+                    alpha: false,
+                };
+                let full = SeamFinder {
+                    incremental: false,
+                    ..finder.clone()
+                };
+
+                for axis in [Axis::Vertical, Axis::Horizontal] {
+                    let masks = Masks::default();
+                    let mut image = image.clone();
+                    let mut cache = SeamCache::new(&finder, &image, axis, &masks);
+                    let mut full_cache = SeamCache::new(&full, &image, axis, &masks);
+
+                    for _ in 0..10 {
+                        let seam = cache.seam().iter().collect::<Vec<_>>();
+                        assert_eq!(seam, full_cache.seam().iter().collect::<Vec<_>>());
+                        assert_eq!(
+                            cache.seam().weights().compacted().as_raw(),
+                            full_cache.seam().weights().compacted().as_raw(),
+                            "{} {:?} {:?}",
+                            energy,
+                            mode,
+                            axis,
+                        );
+
+                        image = carve(&image, axis, seam.iter().copied());
+                        cache.carve(&finder, &image, &masks);
+                        full_cache.carve(&full, &image, &masks);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn seam_guides_attract_seams() {
+        let image = open_image("cat.png");
+        let image = image::imageops::thumbnail(&image, 80, 60);
+        let finder = SeamFinder::default();
+        let find = |guide: Option<SeamGuide>| {
+            let masks = Masks {
+                guide,
+                ..Default::default()
+            };
+            let mut cache = SeamCache::new(&finder, &image, Axis::Vertical, &masks);
+            let seam = cache.seam().iter().collect::<Vec<_>>();
+
+            // Guided seams are recomputed from scratch after every carve.
+            let image = carve(&image, Axis::Vertical, seam.iter().copied());
+            cache.carve(&finder, &image, &masks);
+            (seam, cache.seam().iter().collect::<Vec<_>>())
+        };
+        let guide = |strength: f32| SeamGuide {
+            axis: Axis::Vertical,
+            seam: vec![40; 60],
+            strength,
+        };
+
+        // A guide without any strength changes nothing while a strong guide wins over the energy.
+        let unguided = find(None);
+        assert_eq!(find(Some(guide(0.))), unguided);
+        assert_ne!(unguided.0, vec![40; 60]);
+        assert_eq!(find(Some(guide(1e3))), (vec![40; 60], vec![40; 60]));
+    }
+
+    #[test]
+    fn parallel_seams_match_serial_seams() {
+        let image = open_image("cat.png");
+        let run = |threads: usize, mode: EnergyMode| {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap();
+
+            pool.install(|| {
+                let finder = SeamFinder {
+                    mode,
+                    energy: crate::energy::energy_from_name("sobel", None).unwrap(),
+                    incremental: true,
+                    shape: SeamShape::default(),
+                    alpha: false,
+                };
+                let masks = Masks::default();
+                let mut image = CarvedKernel::from(image.clone());
+                let mut cache = SeamCache::new(&finder, &image, Axis::Vertical, &masks);
+                let mut seams = Vec::new();
+                for _ in 0..3 {
+                    let seam = cache.seam().iter().collect::<Vec<_>>();
+                    image.carve(Axis::Vertical, seam.iter().copied());
+                    cache.carve(&finder, &image, &masks);
+                    seams.push(seam);
+                }
+                (seams, cache.seam().weights().compacted().into_raw())
+            })
+        };
+
+        for mode in [EnergyMode::Backward, EnergyMode::Forward] {
+            assert!(run(1, mode) == run(4, mode), "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn wide_and_loose_seams_match_full_recomputation() {
+        let image = open_image("cat.png");
+        let image = image::imageops::thumbnail(&image, 80, 60);
+
+        for (connectivity, width) in [(3, 1), (1, 3), (2, 2)] {
+            for mode in [EnergyMode::Backward, EnergyMode::Forward] {
+                let finder = SeamFinder {
+                    mode,
+                    energy: crate::energy::energy_from_name("sobel", None).unwrap(),
+                    incremental: true,
+                    shape: SeamShape {
+                        connectivity,
+                        width,
+                    },
+                    alpha: false,
+                };
+                let full = SeamFinder {
+                    incremental: false,
+                    ..finder.clone()
+                };
+
+                let masks = Masks::default();
+                let mut image = image.clone();
+                let mut cache = SeamCache::new(&finder, &image, Axis::Vertical, &masks);
+                let mut full_cache = SeamCache::new(&full, &image, Axis::Vertical, &masks);
+                let mut seams = Vec::new();
+
+                for _ in 0..8 {
+                    let seam = cache.seam().iter().collect::<Vec<_>>();
+                    assert_eq!(seam, full_cache.seam().iter().collect::<Vec<_>>());
+                    assert!(seam
+                        .windows(2)
+                        .all(|pair| (pair[0] - pair[1]).abs() <= connectivity));
+                    assert_eq!(
+                        cache.seam().weights().compacted().as_raw(),
+                        full_cache.seam().weights().compacted().as_raw(),
+                    );
+
+                    image = carve(&image, Axis::Vertical, seam.iter().copied());
+                    cache.carve(&finder, &image, &masks);
+                    full_cache.carve(&full, &image, &masks);
+                    seams.push(seam);
+                }
+
+                // Every pixel of a wide seam is carved along the same path.
+                for group in seams.chunks(width as usize) {
+                    assert!(group.iter().all(|seam| *seam == group[0]));
+                }
+                assert!(width == 1 || seams[0] != seams[width as usize]);
+            }
+        }
+    }
+
+    #[test]
+    fn seam_shapes_are_validated_and_fitted() {
+        let shape = |connectivity, width| SeamShape {
+            connectivity,
+            width,
+        };
+        for invalid in [shape(0, 1), shape(500, 1), shape(1, 0)] {
+            let options = FinderOptions {
+                shape: invalid,
+                ..FinderOptions::default()
+            };
+            assert!(options.build().is_err(), "{:?} is invalid", invalid);
+        }
+
+        assert_eq!(shape(500, 8).fit(5), shape(SeamShape::MAX_CONNECTIVITY, 5));
+        assert_eq!(shape(-2, 0).fit(5), shape(1, 1));
+    }
+
+    #[test]
+    fn transparent_padding_is_carved_first() {
+        use image::Rgba;
+
+        // A flat sprite surrounded by transparent pixels of arbitrary colors.
+        let sprite = Rgba([200, 60, 40, 255]);
+        let image = RgbaImage::from_fn(40, 24, |x, y| {
+            if (14..26).contains(&x) && (4..20).contains(&y) {
+                sprite
+            } else {
+                let noise = ((x * 7919 + y * 104729) % 251) as u8;
+                Rgba([noise, noise.wrapping_mul(3), noise.wrapping_mul(7), 0])
+            }
+        });
+        let sprite_pixels = |image: &RgbaImage| image.pixels().filter(|p| **p == sprite).count();
+
+        let output = Carver::new()
+            .size(Vector2::new(20, 24))
+            .run(image.clone())
+            .unwrap();
+        assert_eq!(sprite_pixels(&output.image), 12 * 16);
+
+        // The arbitrary colors look like edges if transparency is ignored.
+        let ignored = Carver::new()
+            .size(Vector2::new(20, 24))
+            .ignore_alpha()
+            .run(image.clone())
+            .unwrap();
+        assert!(sprite_pixels(&ignored.image) < 12 * 16);
+
+        // Carving stops at the edges of the sprite.
+        let output = Carver::new()
+            .size(Vector2::new(6, 20))
+            .stop_at_opaque()
+            .run(image.clone())
+            .unwrap();
+        assert_eq!(output.image.dimensions(), (12, 20));
+        assert_eq!(output.unreached, Vector2::new(6, 0));
+        assert_eq!(sprite_pixels(&output.image), 12 * 16);
+
+        // Inserting next to a transparent pixel doesn't darken the sprite.
+        let edge = RgbaImage::from_raw(2, 1, [sprite.0, [0, 0, 0, 0]].concat()).unwrap();
+        let mut seams = SeamMask::new(Vector2::new(2, 1));
+        seams.put(Vector2::new(0, 0), Some(0));
+        let grown = insert_blended(&edge, Axis::Vertical, &seams, 1);
+        let Rgba([r, g, b, a]) = *grown.get_pixel(1, 0);
+        assert_eq!(([r, g, b], a), ([200, 60, 40], 128));
+    }
+
+    /// Random images of up to `max` pixels along each axis which are at least two pixels wide.
+    fn arb_image(max: u32) -> impl Strategy<Value = RgbaImage> {
+        (2..=max, 1..=max).prop_flat_map(|(width, height)| {
+            vec(any::<u8>(), (width * height * 4) as usize)
+                .prop_map(move |raw| RgbaImage::from_raw(width, height, raw).unwrap())
+        })
+    }
+
+    /// Random energies of up to `max` pixels along each axis.
+    fn arb_weights(max: u32) -> impl Strategy<Value = WeightImage> {
+        (1..=max, 1..=max).prop_flat_map(|(width, height)| {
+            vec(0f32..100., (width * height) as usize)
+                .prop_map(move |raw| WeightImage::from_raw(width, height, raw).unwrap())
+        })
+    }
+
+    fn arb_axis() -> impl Strategy<Value = Axis> {
+        prop_oneof![Just(Axis::Vertical), Just(Axis::Horizontal)]
+    }
+
+    /// The total weight of the seams removed by carving `steps` out of `image` one at a time.
+    fn order_cost(image: &RgbaImage, finder: &SeamFinder, masks: &Masks, steps: &[Axis]) -> f32 {
+        let mut image = image.clone();
+        let mut masks = masks.clone();
+        let mut cost = 0.;
+        for &axis in steps {
+            let mut weights = finder.weights(&image, axis);
+            masks.apply(&mut weights);
+            let seam = finder.find(&image, weights, axis);
+            cost += seam.weight();
+            image = carve(&image, axis, seam.iter());
+            masks.carve(&seam);
+        }
+        cost
+    }
+
+    #[test]
+    fn optimal_order_is_the_cheapest() {
+        let image = image::imageops::thumbnail(&open_image("cat.png"), 40, 30);
+        let finder = SeamFinder::default();
+        let remove = Vector2::new(6, 5);
+
+        // Seams must squeeze around a protected blob in the middle of the image, which changes which
+        // interleaving is the cheapest.
+        let protect = WeightImage::from_fn(40, 30, |x, y| {
+            Luma([if (5..35).contains(&x) && (5..25).contains(&y) {
+                1.
+            } else {
+                0.
+            }])
+        });
+        let protected = Masks {
+            protect: Some(CarvedKernel::from(protect)),
+            ..Default::default()
+        };
+
+        for masks in [Masks::default(), protected] {
+            let cost = |order: SeamOrder| {
+                let steps = order.plan(&image, &finder, &masks, remove);
+                assert_eq!(
+                    steps.iter().filter(|&&axis| axis == Axis::Vertical).count(),
+                    remove.x as usize
+                );
+                assert_eq!(steps.len(), (remove.x + remove.y) as usize);
+                order_cost(&image, &finder, &masks, &steps)
+            };
+
+            let optimal = cost(SeamOrder::Optimal);
+            for order in [SeamOrder::WidthFirst, SeamOrder::Alternating] {
+                let other = cost(order);
+                assert!(
+                    optimal <= other * (1. + 1e-5),
+                    "optimal order costs {} but {:?} costs {}",
+                    optimal,
+                    order,
+                    other
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn alternating_order_switches_axes() {
+        let plan = SeamOrder::Alternating.plan(
+            &RgbaImage::new(8, 8),
+            &SeamFinder::default(),
+            &Masks::default(),
+            Vector2::new(2, 4),
+        );
+        let (v, h) = (Axis::Vertical, Axis::Horizontal);
+        assert_eq!(plan, vec![v, h, v, h, h, h]);
+    }
+
+    fn transpose(image: &RgbaImage) -> RgbaImage {
+        RgbaImage::from_fn(image.height(), image.width(), |x, y| *image.get_pixel(y, x))
+    }
 
-        // Move there
-        Some(std::mem::replace(&mut self.iter_pos, next_pos)?.x)
+    #[test]
+    fn horizontal_carving_matches_transposed_vertical_carving() {
+        let image = image::imageops::thumbnail(&open_image("cat.png"), 80, 60);
+
+        for energy in ["derivative", "sobel"] {
+            for mode in [EnergyMode::Backward, EnergyMode::Forward] {
+                let carver = || {
+                    Carver::new()
+                        .energy(crate::energy::energy_from_name(energy, None).unwrap())
+                        .energy_mode(mode)
+                };
+                let horizontal = carver().size(Vector2::new(80, 45)).run(image.clone());
+                let vertical = carver().size(Vector2::new(45, 80)).run(transpose(&image));
+
+                assert!(
+                    horizontal.unwrap().image == transpose(&vertical.unwrap().image),
+                    "{} {:?}",
+                    energy,
+                    mode,
+                );
+            }
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn horizontal_carving_is_transposed_vertical_carving(
+            (image, seam) in arb_image(24).prop_flat_map(|image| {
+                let (width, height) = image.dimensions();
+                let seam = vec(0..height as i32, width as usize);
+                (Just(image), seam)
+            })
+        ) {
+            prop_assume!(image.height() > 1);
+            let carved = carve_horizontal(&image, seam.iter().copied());
+            let transposed = carve_vertical(&transpose(&image), seam.iter().copied());
+            prop_assert!(carved == transpose(&transposed));
+        }
+
+        #[test]
+        fn carving_removes_one_pixel_per_row_in_order(
+            (image, seam) in arb_image(24).prop_flat_map(|image| {
+                let (width, height) = image.dimensions();
+                let seam = vec(0..width as i32, height as usize);
+                (Just(image), seam)
+            })
+        ) {
+            let (width, height) = image.dimensions();
+            let carved = carve_vertical(&image, seam.iter().copied());
+            prop_assert_eq!(carved.dimensions(), (width - 1, height));
+
+            // The seam starts from the bottom row.
+            for (y, &remove_at) in (0..height).rev().zip(&seam) {
+                let expected = (0..width)
+                    .filter(|&x| x as i32 != remove_at)
+                    .map(|x| *image.get_pixel(x, y))
+                    .collect::<Vec<_>>();
+                let row = (0..width - 1)
+                    .map(|x| *carved.get_pixel(x, y))
+                    .collect::<Vec<_>>();
+                prop_assert_eq!(row, expected);
+            }
+        }
+
+        #[test]
+        fn seams_are_connected_and_span_the_image(
+            weights in arb_weights(24),
+            axis in arb_axis(),
+        ) {
+            let size = weights.size();
+            let seam = LowestDerivative::find_along(weights, axis);
+            let path = seam.iter().collect::<Vec<_>>();
+
+            prop_assert_eq!(path.len() as i32, axis.along(size));
+            prop_assert!(path.iter().all(|across| (0..axis.across(size)).contains(across)));
+            prop_assert!(path.windows(2).all(|pair| (pair[0] - pair[1]).abs() <= 1));
+        }
+
+        #[test]
+        fn seam_weight_is_the_sum_of_its_energies(
+            weights in arb_weights(24),
+            axis in arb_axis(),
+        ) {
+            let seam = LowestDerivative::find_along(weights.clone(), axis);
+            let sum = seam
+                .positions()
+                .map(|pos| weights.get(pos).0[0])
+                .sum::<f32>();
+
+            // The cascade adds the energies in a different order.
+            prop_assert!(
+                (seam.weight() - sum).abs() <= sum * 1e-5,
+                "seam weighs {} but its energies sum to {}",
+                seam.weight(),
+                sum,
+            );
+        }
+    }
+
+    /// Runs `carver` on a thumbnail of `cat.png` and compares the result to the golden image
+    /// `images/golden/cat_{name}.png`. Colors may differ slightly between platforms so the images
+    /// are compared by their CIELAB distance: the mean distance must stay below 1, about the
+    /// smallest noticeable difference, and at most 1% of the pixels may differ noticeably, which
+    /// leaves room for a seam to shift by a pixel here and there.
+    ///
+    /// Set `SEAM_CARVER_BLESS` to regenerate the golden images after an intended change in output.
+    fn assert_matches_golden(name: &str, carver: Carver) {
+        let image = image::imageops::thumbnail(&open_image("cat.png"), 120, 120);
+        let output = carver.run(image).unwrap().image;
+
+        let path = format!(
+            "{}/images/golden/cat_{}.png",
+            env!("CARGO_MANIFEST_DIR"),
+            name
+        );
+        if std::env::var_os("SEAM_CARVER_BLESS").is_some() {
+            output.save(&path).unwrap();
+            return;
+        }
+
+        let golden = image::open(&path).unwrap().into_rgba8();
+        assert_eq!(output.dimensions(), golden.dimensions(), "{}", name);
+
+        let lab = |pixel| srgb_to_lab(rgba_to_vec4(pixel).truncate());
+        let distances = output
+            .pixels()
+            .zip(golden.pixels())
+            .map(|(a, b)| (lab(a) - lab(b)).magnitude())
+            .collect::<Vec<_>>();
+        let mean = distances.iter().sum::<f32>() / distances.len() as f32;
+        let noticeable = distances.iter().filter(|&&distance| distance > 2.3).count();
+
+        assert!(mean < 1., "{} is off by {} on average", name, mean);
+        assert!(
+            noticeable * 100 <= distances.len(),
+            "{} has {} noticeably different pixels",
+            name,
+            noticeable,
+        );
+    }
+
+    #[test]
+    fn carving_matches_golden_images() {
+        assert_matches_golden("narrow", Carver::new().size(Vector2::new(90, 120)));
+        assert_matches_golden(
+            "forward",
+            Carver::new()
+                .size(Vector2::new(100, 95))
+                .energy_mode(EnergyMode::Forward),
+        );
+        assert_matches_golden("wide", Carver::new().size(Vector2::new(150, 135)));
     }
 }
@@ -0,0 +1,742 @@
+//! The command line interface of the seam carver: the definition of its arguments and their
+//! conversion into the options of the library.
+
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use seam_carver::batch::DEFAULT_TEMPLATE;
+use seam_carver::carver::{
+    EnergyMode, FinderOptions, SeamFinder, SeamOrder, SeamShape, ENERGY_MODE_NAMES,
+    SEAM_ORDER_NAMES,
+};
+use seam_carver::emit::EnergySteps;
+use seam_carver::energy::{ColorSpace, EnergyFilter, Saliency, COLOR_SPACE_NAMES, ENERGY_NAMES};
+use seam_carver::error::{CarveError, CarveResult};
+use seam_carver::job::Job;
+use seam_carver::resize::TargetSize;
+use seam_carver::stream::parse_memory_size;
+use std::fmt::Display;
+use std::str::FromStr;
+
+// === Strings === //
+const ARG_IMG_PATH_HINT: &str = "path";
+
+const EMIT_SEAMS_ORIGINAL_HELP: &str =
+    "Emits the seams used by the carver over the original image. The color of the seams determine \
+     when they were carved, with green being earlier than red.";
+
+const EMIT_SEAMS_WEIGHTS_HELP: &str =
+    "Emits the seams used by the carver over an image of the weights. The color of the seams \
+     determine when they were carved, with green being earlier than red.";
+
+// === Parsing === //
+/// Parses the value of the argument `name`, if it was given.
+pub fn parse_arg<T>(args: &ArgMatches, name: &str) -> CarveResult<Option<T>>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    args.value_of(name)
+        .map(|arg| parse_value(name, arg))
+        .transpose()
+}
+
+/// Parses every value of the argument `name`.
+fn parse_args<T>(args: &ArgMatches, name: &str) -> CarveResult<Vec<T>>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    args.values_of(name)
+        .into_iter()
+        .flatten()
+        .map(|arg| parse_value(name, arg))
+        .collect()
+}
+
+/// Parses `arg`, the value of the argument `name`. Clap's validators reject most bad values before
+/// this point, but those which slip through are reported rather than panicking.
+pub fn parse_value<T>(name: &str, arg: &str) -> CarveResult<T>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    arg.parse().map_err(|err| invalid_value(name, arg, err))
+}
+
+fn invalid_value(name: &str, arg: &str, reason: impl Display) -> CarveError {
+    CarveError::InvalidInput(format!(
+        "Invalid {} {:?}: {}",
+        name.replace('_', " "),
+        arg,
+        reason
+    ))
+}
+
+// === Shared arguments === //
+fn finder_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("energy")
+            .long("energy")
+            .value_name("FUNCTION")
+            .possible_values(ENERGY_NAMES)
+            .default_value("derivative")
+            .help("The function used to determine the energy of each pixel.")
+            .long_help(
+                "The function used to determine the energy of each pixel. `derivative` is the \
+                 difference between the pixels on either side of the seam, `sobel` and \
+                 `scharr` are the magnitudes of the 2D Sobel and Scharr gradients, `l1` is \
+                 the L1 norm of the gradient, `entropy` is the entropy of the brightness \
+                 around each pixel, and `hog` is the L1 gradient divided by the histogram of \
+                 oriented gradients around each pixel. `spectral-residual` and \
+                 `center-surround` are saliency energies which favor the regions which stand \
+                 out from the rest of the image, see `--saliency`.",
+            ),
+        Arg::with_name("energy_radius")
+            .long("energy-radius")
+            .value_name("RADIUS")
+            .help(
+                "The radius of the window used by the `entropy`, `hog` and `center-surround` \
+                 energy functions.",
+            )
+            .validator(|arg| match arg.parse::<i32>() {
+                Ok(radius) if radius >= 0 => Ok(()),
+                _ => Err("Radius must be a non-negative integer.".to_string()),
+            }),
+        Arg::with_name("energy_mode")
+            .long("energy-mode")
+            .value_name("MODE")
+            .possible_values(ENERGY_MODE_NAMES)
+            .default_value("backward")
+            .help("Whether seams are weighed by the energy they remove or insert.")
+            .long_help(
+                "Whether seams are weighed by the energy they remove or insert. `backward` \
+                 sums up the energy of the pixels along the seam. `forward` sums up the energy \
+                 of the new edges which are created once the seam is removed, which avoids \
+                 the jagged artifacts caused by backward energy. In `forward` mode, \
+                 `--emit-sobel` emits the cumulative seam weights instead.",
+            ),
+        Arg::with_name("energy_color")
+            .long("energy-color")
+            .value_name("SPACE")
+            .possible_values(COLOR_SPACE_NAMES)
+            .default_value("rgba")
+            .help("The color space in which the gradient energy functions compare pixels.")
+            .long_help(
+                "The color space in which the `derivative`, `sobel`, `scharr` and `l1` energy \
+                 functions compare neighboring pixels. `rgba` compares the raw pixels, `rgb` \
+                 ignores alpha, `luma` only compares brightness, and `lab` and `oklab` \
+                 compare the perceptual difference (ΔE) between colors, so that flat regions \
+                 really are flat and edges between colors of equal lightness are still \
+                 found. Only applies to `backward` energy.",
+            ),
+        Arg::with_name("lightness_weight")
+            .long("lightness-weight")
+            .value_name("WEIGHT")
+            .default_value("1")
+            .help("Scales differences in lightness in the `lab` and `oklab` color spaces.")
+            .validator(|arg| match arg.parse::<f32>() {
+                Ok(weight) if weight >= 0. => Ok(()),
+                _ => Err("Weight must be a non-negative number.".to_string()),
+            }),
+        Arg::with_name("saliency")
+            .long("saliency")
+            .value_name("NAME:WEIGHT")
+            .help("Blends a saliency energy into the energy function.")
+            .long_help(
+                "Blends a saliency energy into the energy function. The energy of every pixel \
+                 becomes `(1 - WEIGHT) * energy + WEIGHT * saliency`. `spectral-residual` \
+                 compares the spectrum of the image with its local average and \
+                 `center-surround` compares the CIELAB color of every pixel with its \
+                 surroundings within `--energy-radius`. Only applies to `backward` energy.",
+            )
+            .validator(|arg| {
+                arg.parse::<Saliency>()?;
+                Ok(())
+            }),
+        Arg::with_name("energy_filter")
+            .long("energy-filter")
+            .value_name("STAGE:RADIUS")
+            .multiple(true)
+            .number_of_values(1)
+            .use_delimiter(true)
+            .help("Filters the energy of the image before finding seams.")
+            .long_help(
+                "Filters the energy of the image before finding seams, without filtering the \
+                 image itself. `blur` applies a Gaussian blur, `dilate` takes the maximum \
+                 energy and `median` takes the median energy within the radius of every \
+                 pixel. Stages are applied in the order in which they are given, e.g. \
+                 `--energy-filter median:1,blur:3`. Only applies to `backward` energy.",
+            )
+            .validator(|arg| {
+                arg.parse::<EnergyFilter>()?;
+                Ok(())
+            }),
+        Arg::with_name("seam_connectivity")
+            .long("seam-connectivity")
+            .value_name("PIXELS")
+            .default_value("1")
+            .help("The number of pixels by which seams may move from one line to the next.")
+            .long_help(
+                "The number of pixels by which seams may move from one line to the next. \
+                 Looser seams can follow diagonal structures rather than cutting through \
+                 them.",
+            )
+            .validator(|arg| match arg.parse::<i32>() {
+                Ok(pixels) if (1..=SeamShape::MAX_CONNECTIVITY).contains(&pixels) => Ok(()),
+                _ => Err(format!(
+                    "Connectivity must be an integer between 1 and {}.",
+                    SeamShape::MAX_CONNECTIVITY
+                )),
+            }),
+        Arg::with_name("seam_width")
+            .long("seam-width")
+            .value_name("PIXELS")
+            .default_value("1")
+            .help("The number of adjacent pixels removed from every line by each seam.")
+            .long_help(
+                "The number of adjacent pixels removed from every line by each seam. Wide \
+                 seams are found once and then carved out one pixel at a time, which speeds \
+                 up large reductions at the cost of precision.",
+            )
+            .validator(|arg| match arg.parse::<i32>() {
+                Ok(pixels) if pixels >= 1 => Ok(()),
+                _ => Err("Seam width must be a positive integer.".to_string()),
+            }),
+    ]
+}
+
+pub fn parse_finder(args: &ArgMatches, incremental: bool) -> CarveResult<SeamFinder> {
+    // Every one of these arguments has a default value.
+    let value = |name: &str| args.value_of(name).unwrap();
+    let unknown = |name: &str| invalid_value(name, value(name), "unknown name");
+    FinderOptions {
+        mode: EnergyMode::from_name(value("energy_mode")).ok_or_else(|| unknown("energy_mode"))?,
+        energy: value("energy").to_string(),
+        radius: parse_arg(args, "energy_radius")?,
+        color: ColorSpace::from_name(
+            value("energy_color"),
+            parse_value("lightness_weight", value("lightness_weight"))?,
+        )
+        .ok_or_else(|| unknown("energy_color"))?,
+        saliency: parse_arg(args, "saliency")?,
+        filters: parse_args(args, "energy_filter")?,
+        shape: SeamShape {
+            connectivity: parse_value("seam_connectivity", value("seam_connectivity"))?,
+            width: parse_value("seam_width", value("seam_width"))?,
+        },
+        incremental,
+    }
+    .build()
+}
+
+fn order_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("order")
+        .long("order")
+        .value_name("ORDER")
+        .possible_values(SEAM_ORDER_NAMES)
+        .default_value("width-first")
+        .help("The order in which vertical and horizontal seams are removed.")
+        .long_help(
+            "The order in which vertical and horizontal seams are removed when both \
+             dimensions shrink. `width-first` removes all the columns before the rows, \
+             `alternating` switches between the two axes, and `optimal` finds the \
+             cheapest interleaving using a transport map. Note that `optimal` finds a \
+             seam for every intermediate image size and is therefore much slower.",
+        )
+}
+
+fn ignore_alpha_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("ignore_alpha")
+        .long("ignore-alpha")
+        .help("Treats transparent pixels like any other pixel.")
+        .long_help(
+            "Treats transparent pixels like any other pixel. By default, images and videos \
+             with at least one pixel which isn't fully opaque are detected automatically: \
+             their energy is computed from premultiplied colors and scaled by the opacity of \
+             every pixel, so that the transparent padding around sprites is carved out \
+             first. Pass this flag to carve such images the way fully opaque ones are.",
+        )
+}
+
+pub fn parse_order(args: &ArgMatches) -> CarveResult<SeamOrder> {
+    let order = args.value_of("order").unwrap();
+    SeamOrder::from_name(order).ok_or_else(|| invalid_value("order", order, "unknown order"))
+}
+
+// === App definition === //
+pub fn app() -> App<'static, 'static> {
+    App::new("Seam Carver")
+        .author(clap::crate_authors!())
+        .version(clap::crate_version!())
+        .arg(
+            Arg::with_name("timings")
+                .short("v")
+                .long("timings")
+                .help("Displays the timings of the operations."),
+        )
+        .arg(
+            Arg::with_name("job")
+                .long("job")
+                .value_name(ARG_IMG_PATH_HINT)
+                .help("Reads the arguments of the run from a TOML or JSON job file.")
+                .long_help(
+                    "Reads the arguments of the run from a TOML or JSON job file. `inputs` and \
+                     `sizes` list every image to resize and every size to resize them to, and \
+                     every other key is the long name of an argument (e.g. \
+                     `energy-mode = \"forward\"` or `full-recompute = true`). Paths are \
+                     relative to the job file and are output templates like batch outputs. \
+                     Arguments passed on the command line override the job file's. Files ending \
+                     with `.json` are parsed as JSON and every other file is parsed as TOML.",
+                ),
+        )
+        .arg(
+            Arg::with_name("dry_run")
+                .long("dry-run")
+                .help("Prints the resolved job and the planned resizes without resizing anything."),
+        )
+        // Simple use arguments
+        .arg(
+            Arg::with_name("input")
+                .short("i")
+                .long("in")
+                .value_name(ARG_IMG_PATH_HINT)
+                .help("Path to the image to be resized.")
+                .long_help(
+                    "Path to the image to be resized. Animated GIFs and numbered frame sequences \
+                     (e.g. `frames/%04d.png`, starting at frame 0 or 1) are resized frame by \
+                     frame. The output of a video must either be a GIF or a frame sequence.",
+                )
+                .required_unless("job"),
+        )
+        .arg(
+            Arg::with_name("to_size")
+                .short("s")
+                .long("size")
+                .value_name("WIDTHxHEIGHT")
+                .help("The dimensions to which the image will be resized.")
+                .long_help(
+                    "The dimensions to which the image will be resized. \
+                     Components are absolute by default but can be made \
+                     relative with a leading `?` (e.g. `?20x?-30`) and \
+                     preserving with `P` (e.g. `300xP`).",
+                )
+                .validator(|arg| {
+                    arg.parse::<TargetSize>()?;
+                    Ok(())
+                })
+                .required_unless_one(&["remove_mask", "job"]),
+        )
+        .arg(
+            Arg::with_name("remove_mask")
+                .long("remove-mask")
+                .value_name(ARG_IMG_PATH_HINT)
+                .help("Removes the object marked by the non-zero pixels of the specified mask.")
+                .long_help(
+                    "Removes the object marked by the non-zero pixels of the specified mask, which \
+                     must have the same dimensions as the input image. Every non-zero pixel counts \
+                     as fully marked, however faint. Seams are carved through the marked pixels \
+                     until none of them remain. The image is then resized to \
+                     `--size` if it is specified, so passing `--size PxP` re-inserts seams until \
+                     the image is back to its original size.",
+                ),
+        )
+        .arg(
+            Arg::with_name("protect_mask")
+                .long("protect-mask")
+                .value_name(ARG_IMG_PATH_HINT)
+                .help("Prevents seams from crossing the non-zero pixels of the specified mask.")
+                .long_help(
+                    "Prevents seams from crossing the non-zero pixels of the specified mask, which \
+                     must have the same dimensions as the input image. Grayscale masks are \
+                     supported: the brighter the pixel, the more expensive it is for a seam to \
+                     cross it.",
+                ),
+        )
+        .arg(order_arg())
+        .args(&finder_args())
+        .arg(ignore_alpha_arg())
+        .arg(
+            Arg::with_name("stop_at_opaque")
+                .long("stop-at-opaque")
+                .help("Stops shrinking an axis once every seam would cross opaque content.")
+                .long_help(
+                    "Stops shrinking an axis once every seam along it would have to cross opaque \
+                     content (i.e. pixels which are at least half opaque) rather than distorting \
+                     that content. The image is saved at the size which was reached. Only \
+                     useful for images with transparency.",
+                ),
+        )
+        .arg(
+            Arg::with_name("full_recompute")
+                .long("full-recompute")
+                .help("Recomputes the energy of every pixel after every carve.")
+                .long_help(
+                    "Recomputes the energy of every pixel and the weight of every seam after every \
+                     carve rather than only updating the pixels next to the removed seam. This \
+                     produces the exact same output and is only useful for debugging.",
+                ),
+        )
+        .arg(
+            Arg::with_name("threads")
+                .short("j")
+                .long("threads")
+                .value_name("COUNT")
+                .help("The number of threads used to compute the energy and seams of the image.")
+                .long_help(
+                    "The number of threads used to compute the energy and seams of the image. \
+                     Defaults to the number of logical cores. The output is the same regardless \
+                     of the number of threads.",
+                )
+                .validator(|arg| match arg.parse::<usize>() {
+                    Ok(threads) if threads > 0 => Ok(()),
+                    _ => Err("Thread count must be a positive integer.".to_string()),
+                }),
+        )
+        .arg(
+            Arg::with_name("coherence")
+                .long("coherence")
+                .value_name("STRENGTH")
+                .help("How strongly the seams of a video stick to those of the previous frame.")
+                .long_help(
+                    "How strongly the seams of a video stick to those of the previous frame. Every \
+                     pixel's energy is increased by this amount per pixel of distance from the \
+                     seam carved at the same step of the previous frame, which keeps the output \
+                     from flickering. `0` carves every frame independently.",
+                )
+                .validator(|arg| match arg.parse::<f32>() {
+                    Ok(strength) if strength >= 0. => Ok(()),
+                    _ => Err("Coherence must be a non-negative number.".to_string()),
+                }),
+        )
+        .arg(
+            Arg::with_name("output")
+                .short("o")
+                .long("out")
+                .value_name(ARG_IMG_PATH_HINT)
+                .help("Output image path. Omitting this argument will disable output saving."),
+        )
+        // Debug emit flags
+        .arg(
+            Arg::with_name("emit_sobel")
+                .short("W")
+                .long("emit-sobel")
+                .value_name(ARG_IMG_PATH_HINT)
+                .help("Emits the result of the sobel filter, which determines the 'utility' of each \
+                       pixel, at specified carving steps. Place a colon followed by a list of numbers \
+                       (e.g. \"--emit-seams=foo.png:1,2,3\") to specify when in the resize these seam \
+                       images should be emitted.")
+                .validator(|arg| {
+                    arg.parse::<EnergySteps>()?;
+                    Ok(())
+                }),
+        )
+        .arg(
+            Arg::with_name("emit_seams_original")
+                .long("emit-seams-on-original")
+                .value_name(ARG_IMG_PATH_HINT)
+                .help(EMIT_SEAMS_ORIGINAL_HELP),
+        )
+        .arg(
+            Arg::with_name("emit_seams_weights")
+                .short("S")
+                .long("emit-seams")
+                .value_name(ARG_IMG_PATH_HINT)
+                .help(EMIT_SEAMS_WEIGHTS_HELP),
+        )
+        .arg(
+            Arg::with_name("emit_animation")
+                .long("emit-animation")
+                .value_name(ARG_IMG_PATH_HINT)
+                .help("Emits an animated GIF showing every carving pass.")
+                .long_help(
+                    "Emits an animated GIF showing every carving pass. Every frame shows the \
+                     current image with the seam about to be removed (or the seams about to be \
+                     inserted) highlighted in red.",
+                ),
+        )
+        .arg(
+            Arg::with_name("animation_every")
+                .long("animation-every")
+                .value_name("PASSES")
+                .requires("emit_animation")
+                .help("Only records one frame of the animation every specified number of passes.")
+                .validator(|arg| match arg.parse::<usize>() {
+                    Ok(passes) if passes > 0 => Ok(()),
+                    _ => Err("Pass count must be a positive integer.".to_string()),
+                }),
+        )
+        .arg(
+            Arg::with_name("animation_energy")
+                .long("animation-energy")
+                .requires("emit_animation")
+                .help("Shows the energy of the image next to it in the animation."),
+        )
+        // Index subcommands
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .subcommand(
+            SubCommand::with_name("index")
+                .about("Records when every pixel is removed while carving an image to one column.")
+                .long_about(
+                    "Carves the image down to a single column once and records the step at which \
+                     every pixel is removed. `apply` can then resize the image to any narrower \
+                     width without finding any seams.",
+                )
+                .arg(
+                    Arg::with_name("input")
+                        .short("i")
+                        .long("in")
+                        .value_name(ARG_IMG_PATH_HINT)
+                        .help("Path to the image to be indexed.")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("out")
+                        .value_name(ARG_IMG_PATH_HINT)
+                        .help("Path to the index.")
+                        .long_help(
+                            "Path to the index. Paths ending with `.png` are saved as 16-bit \
+                             grayscale PNGs (or 32-bit RGBA PNGs for very wide images) and every \
+                             other path is saved as a raw binary file.",
+                        )
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("protect_mask")
+                        .long("protect-mask")
+                        .value_name(ARG_IMG_PATH_HINT)
+                        .help("Prevents seams from crossing the non-zero pixels of the specified mask."),
+                )
+                .args(&finder_args())
+                .arg(ignore_alpha_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("apply")
+                .about("Resizes an image to a narrower width using an index built by `index`.")
+                .arg(
+                    Arg::with_name("input")
+                        .short("i")
+                        .long("in")
+                        .value_name(ARG_IMG_PATH_HINT)
+                        .help("Path to the image which was indexed.")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("index")
+                        .short("x")
+                        .long("index")
+                        .value_name(ARG_IMG_PATH_HINT)
+                        .help("Path to the image's index.")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("width")
+                        .short("w")
+                        .long("width")
+                        .value_name("WIDTH")
+                        .help("The width to which the image will be resized.")
+                        .validator(|arg| match arg.parse::<i32>() {
+                            Ok(width) if width > 0 => Ok(()),
+                            _ => Err("Width must be a positive integer.".to_string()),
+                        })
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("out")
+                        .value_name(ARG_IMG_PATH_HINT)
+                        .help("Output image path.")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("batch")
+                .about("Resizes many images to many sizes in parallel.")
+                .long_about(
+                    "Resizes every input image to every size in parallel and prints a summary of \
+                     the timings and failures. Every image is only loaded once and sizes which \
+                     only shrink the same axis of an image are produced by a single carve.",
+                )
+                .arg(
+                    Arg::with_name("input")
+                        .short("i")
+                        .long("in")
+                        .value_name(ARG_IMG_PATH_HINT)
+                        .help("Images, directories or globs (e.g. `photos/*.png`) to resize.")
+                        .multiple(true)
+                        .number_of_values(1)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("to_size")
+                        .short("s")
+                        .long("size")
+                        .value_name("WIDTHxHEIGHT")
+                        .help("The dimensions to which every image will be resized.")
+                        .long_help(
+                            "The dimensions to which every image will be resized, in the same \
+                             form as `--size`. Can be specified multiple times or as a \
+                             comma-separated list.",
+                        )
+                        .multiple(true)
+                        .use_delimiter(true)
+                        .validator(|arg| {
+                            arg.parse::<TargetSize>()?;
+                            Ok(())
+                        })
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("out")
+                        .value_name("TEMPLATE")
+                        .default_value(DEFAULT_TEMPLATE)
+                        .help("Output path template.")
+                        .long_help(
+                            "Output path template. `{dir}`, `{stem}` and `{ext}` are replaced \
+                             with the directory, file stem and extension of the input and `{w}` \
+                             and `{h}` with the size of the output. Missing directories are \
+                             created.",
+                        ),
+                )
+                .arg(order_arg())
+                .args(&finder_args())
+                .arg(ignore_alpha_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("stream")
+                .about("Shrinks PNG images which are too large to fit in memory.")
+                .long_about(
+                    "Shrinks a PNG image while only holding a strip of its lines in memory. The \
+                     pixels, energy and seam choices of the image are kept in scratch files, which \
+                     take up 9 bytes per pixel on disk (13 for 16-bit images). Every seam costs a \
+                     pass over these files so this is much slower than carving in memory. \
+                     Streaming finds the same seams as the `width-first` order but can't grow \
+                     images, use masks, forward energy or the saliency energies. 8 and 16-bit \
+                     images are carved and saved at their own depth.",
+                )
+                .arg(
+                    Arg::with_name("input")
+                        .short("i")
+                        .long("in")
+                        .value_name(ARG_IMG_PATH_HINT)
+                        .help("Path to the PNG image to be carved.")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("out")
+                        .value_name(ARG_IMG_PATH_HINT)
+                        .help("Path to the carved PNG image.")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("to_size")
+                        .short("s")
+                        .long("size")
+                        .value_name("WIDTHxHEIGHT")
+                        .help(
+                            "The dimensions to which the image will be shrunk, in the same form as \
+                             `--size`.",
+                        )
+                        .validator(|arg| {
+                            arg.parse::<TargetSize>()?;
+                            Ok(())
+                        })
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("memory_budget")
+                        .long("memory-budget")
+                        .value_name("BYTES")
+                        .default_value("256M")
+                        .help("The amount of memory used to hold strips of the image.")
+                        .long_help(
+                            "The amount of memory used to hold strips of the image, with an \
+                             optional `K`, `M` or `G` suffix. Larger budgets read and write more \
+                             lines at once.",
+                        )
+                        .validator(|arg| parse_memory_size(&arg).map(|_| ())),
+                )
+                .arg(
+                    Arg::with_name("scratch_dir")
+                        .long("scratch-dir")
+                        .value_name("DIR")
+                        .help(
+                            "The directory in which scratch files are created. Defaults to the \
+                             system's temporary directory.",
+                        ),
+                )
+                .args(&finder_args())
+                .arg(ignore_alpha_arg()),
+        )
+}
+
+// === Job files === //
+pub fn load_job(args: &ArgMatches) -> CarveResult<Job> {
+    let mut job = args
+        .value_of("job")
+        .map(Job::load)
+        .transpose()?
+        .unwrap_or_default();
+
+    // Defaults don't override the job file.
+    let passed = |name: &str| {
+        args.value_of(name)
+            .filter(|_| args.occurrences_of(name) > 0)
+    };
+    fn set<T>(args: &ArgMatches, field: &mut Option<T>, name: &str) -> CarveResult<()>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        if args.occurrences_of(name) > 0 {
+            *field = parse_arg(args, name)?;
+        }
+        Ok(())
+    }
+
+    if let Some(input) = passed("input") {
+        job.inputs = vec![input.to_string()];
+    }
+    if let Some(size) = passed("to_size") {
+        job.sizes = vec![size.parse().map_err(CarveError::InvalidSize)?];
+    }
+    if args.occurrences_of("energy_filter") > 0 {
+        job.energy_filter = parse_args(args, "energy_filter")?;
+    }
+    set(args, &mut job.out, "output")?;
+    set(args, &mut job.order, "order")?;
+    set(args, &mut job.energy, "energy")?;
+    set(args, &mut job.energy_radius, "energy_radius")?;
+    set(args, &mut job.energy_mode, "energy_mode")?;
+    set(args, &mut job.energy_color, "energy_color")?;
+    set(args, &mut job.lightness_weight, "lightness_weight")?;
+    set(args, &mut job.saliency, "saliency")?;
+    set(args, &mut job.seam_connectivity, "seam_connectivity")?;
+    set(args, &mut job.seam_width, "seam_width")?;
+    set(args, &mut job.protect_mask, "protect_mask")?;
+    set(args, &mut job.remove_mask, "remove_mask")?;
+    set(args, &mut job.coherence, "coherence")?;
+    set(args, &mut job.threads, "threads")?;
+    set(args, &mut job.emit_sobel, "emit_sobel")?;
+    set(args, &mut job.emit_seams, "emit_seams_weights")?;
+    set(args, &mut job.emit_seams_on_original, "emit_seams_original")?;
+    set(args, &mut job.emit_animation, "emit_animation")?;
+    set(args, &mut job.animation_every, "animation_every")?;
+
+    for (field, name) in [
+        (&mut job.ignore_alpha, "ignore_alpha"),
+        (&mut job.stop_at_opaque, "stop_at_opaque"),
+        (&mut job.full_recompute, "full_recompute"),
+        (&mut job.timings, "timings"),
+        (&mut job.animation_energy, "animation_energy"),
+    ] {
+        *field |= args.is_present(name);
+    }
+
+    Ok(job)
+}
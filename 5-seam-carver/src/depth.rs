@@ -0,0 +1,247 @@
+use crate::error::{CarveResult, PathContext};
+use crate::util::{
+    rgba_to_vec4, vec4_to_rgba, ColorKernel, ImageBufferVec, KernelRect, StaticPixel,
+};
+use cgmath::{Vector2, Vector4};
+use image::codecs::hdr::{HdrDecoder, HdrEncoder};
+use image::{open, DynamicImage, ImageFormat, Rgb, Rgba, RgbaImage};
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+
+pub type Rgba16Image = ImageBufferVec<Rgba<u16>>;
+pub type Rgba32FImage = ImageBufferVec<Rgba<f32>>;
+
+/// A pixel type which images can be carved at. Colors are exchanged as vectors whose components
+/// lie between `0` and `1`, except for floating-point pixels which are passed through as is.
+pub trait ColorPixel: StaticPixel + Send + Sync {
+    fn to_vec4(&self) -> Vector4<f32>;
+
+    fn from_vec4(vec: Vector4<f32>) -> Self;
+
+    /// Saves an image of this pixel type at the closest depth the format of `path` supports.
+    fn save(image: &ImageBufferVec<Self>, path: &Path) -> CarveResult<()>;
+}
+
+impl ColorPixel for Rgba<u8> {
+    fn to_vec4(&self) -> Vector4<f32> {
+        rgba_to_vec4(self)
+    }
+
+    fn from_vec4(vec: Vector4<f32>) -> Self {
+        vec4_to_rgba(vec)
+    }
+
+    fn save(image: &ImageBufferVec<Self>, path: &Path) -> CarveResult<()> {
+        image.save(path).writing(path)
+    }
+}
+
+impl ColorPixel for Rgba<u16> {
+    fn to_vec4(&self) -> Vector4<f32> {
+        Vector4::from(self.0).cast::<f32>().unwrap() / u16::MAX as f32
+    }
+
+    fn from_vec4(vec: Vector4<f32>) -> Self {
+        // Unlike 8-bit channels, truncating would lose values to the precision of `f32`.
+        let vec = (vec * u16::MAX as f32).map(f32::round);
+        Rgba([vec.x as u16, vec.y as u16, vec.z as u16, vec.w as u16])
+    }
+
+    fn save(image: &ImageBufferVec<Self>, path: &Path) -> CarveResult<()> {
+        match ImageFormat::from_path(path) {
+            Ok(ImageFormat::Png | ImageFormat::Tiff | ImageFormat::Farbfeld) => {
+                image.save(path).writing(path)
+            }
+            _ => Rgba::<u8>::save(&convert(image), path),
+        }
+    }
+}
+
+impl ColorPixel for Rgba<f32> {
+    fn to_vec4(&self) -> Vector4<f32> {
+        Vector4::from(self.0)
+    }
+
+    fn from_vec4(vec: Vector4<f32>) -> Self {
+        Rgba(vec.into())
+    }
+
+    /// Radiance HDR files keep the full range of the image but drop its alpha channel. Every other
+    /// format receives the image clamped to `0..=1`.
+    fn save(image: &ImageBufferVec<Self>, path: &Path) -> CarveResult<()> {
+        if !matches!(ImageFormat::from_path(path), Ok(ImageFormat::Hdr)) {
+            return Rgba::<u16>::save(&convert(image), path);
+        }
+
+        let file = File::create(path).writing(path)?;
+        let pixels = image
+            .pixels()
+            .map(|pixel| Rgb([pixel.0[0], pixel.0[1], pixel.0[2]]))
+            .collect::<Vec<_>>();
+        HdrEncoder::new(BufWriter::new(file))
+            .encode(&pixels, image.width() as usize, image.height() as usize)
+            .writing(path)
+    }
+}
+
+/// Converts an image between pixel types.
+pub fn convert<P: ColorPixel, Q: ColorPixel>(image: &ImageBufferVec<P>) -> ImageBufferVec<Q> {
+    ImageBufferVec::from_fn(image.width(), image.height(), |x, y| {
+        Q::from_vec4(image.get_pixel(x, y).to_vec4())
+    })
+}
+
+/// An image loaded at the depth of its file so that it can be carved and saved without losing
+/// precision.
+#[derive(Debug, Clone)]
+pub enum DepthImage {
+    Rgba8(RgbaImage),
+    Rgba16(Rgba16Image),
+    /// Only Radiance HDR files are loaded as floating-point images. Their alpha is always `1`.
+    Rgba32F(Rgba32FImage),
+}
+
+impl DepthImage {
+    /// Loads an image, keeping 16-bit channels and the floating-point channels of HDR files.
+    pub fn open(path: impl AsRef<Path>) -> CarveResult<Self> {
+        let path = path.as_ref();
+        if matches!(ImageFormat::from_path(path), Ok(ImageFormat::Hdr)) {
+            let file = File::open(path).reading(path)?;
+            let decoder = HdrDecoder::new(BufReader::new(file)).reading(path)?;
+            let meta = decoder.metadata();
+            let pixels = decoder
+                .read_image_hdr()
+                .reading(path)?
+                .into_iter()
+                .flat_map(|Rgb([r, g, b])| [r, g, b, 1.])
+                .collect();
+            return Ok(DepthImage::Rgba32F(
+                Rgba32FImage::from_raw(meta.width, meta.height, pixels).unwrap(),
+            ));
+        }
+
+        Ok(match open(path).reading(path)? {
+            image @ (DynamicImage::ImageLuma16(_)
+            | DynamicImage::ImageLumaA16(_)
+            | DynamicImage::ImageRgb16(_)
+            | DynamicImage::ImageRgba16(_)) => DepthImage::Rgba16(image.into_rgba16()),
+            image => DepthImage::Rgba8(image.into_rgba8()),
+        })
+    }
+
+    /// Saves the image at its own depth if the format of `path` supports it. See
+    /// [ColorPixel::save].
+    pub fn save(&self, path: impl AsRef<Path>) -> CarveResult<()> {
+        let path = path.as_ref();
+        match self {
+            DepthImage::Rgba8(image) => ColorPixel::save(image, path),
+            DepthImage::Rgba16(image) => ColorPixel::save(image, path),
+            DepthImage::Rgba32F(image) => ColorPixel::save(image, path),
+        }
+    }
+
+    /// Converts the image to 8 bits per channel, e.g. for debug views.
+    pub fn to_rgba8(&self) -> RgbaImage {
+        match self {
+            DepthImage::Rgba8(image) => image.clone(),
+            DepthImage::Rgba16(image) => convert(image),
+            DepthImage::Rgba32F(image) => convert(image),
+        }
+    }
+
+    /// The image as a kernel, whatever its depth.
+    pub fn kernel(&self) -> &dyn ColorKernel {
+        match self {
+            DepthImage::Rgba8(image) => image,
+            DepthImage::Rgba16(image) => image,
+            DepthImage::Rgba32F(image) => image,
+        }
+    }
+}
+
+impl From<RgbaImage> for DepthImage {
+    fn from(image: RgbaImage) -> Self {
+        DepthImage::Rgba8(image)
+    }
+}
+
+impl KernelRect for DepthImage {
+    fn size(&self) -> Vector2<i32> {
+        self.kernel().size()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resize::Carver;
+    use image::imageops::thumbnail;
+
+    /// Checks that every row of `carved` only keeps pixels of the same row of `image`, in order.
+    fn is_carved_from<P: ColorPixel + PartialEq>(
+        carved: &ImageBufferVec<P>,
+        image: &ImageBufferVec<P>,
+    ) -> bool {
+        carved.rows().zip(image.rows()).all(|(carved, row)| {
+            let mut row = row;
+            carved
+                .into_iter()
+                .all(|pixel| row.any(|other| other == pixel))
+        })
+    }
+
+    #[test]
+    fn deep_images_keep_their_precision() {
+        for value in 0..=u16::MAX {
+            let pixel = Rgba([value; 4]);
+            assert_eq!(Rgba::<u16>::from_vec4(pixel.to_vec4()), pixel);
+        }
+        for value in 0..=u8::MAX {
+            let pixel = Rgba([value; 4]);
+            assert_eq!(Rgba::<u8>::from_vec4(pixel.to_vec4()), pixel);
+        }
+
+        // The low bytes of the channels carry detail which an 8-bit pipeline would drop.
+        let path = format!("{}/images/cat.png", env!("CARGO_MANIFEST_DIR"));
+        let image = thumbnail(&open(path).unwrap().into_rgba8(), 64, 48);
+        let deep = Rgba16Image::from_fn(64, 48, |x, y| {
+            let Rgba([r, g, b, a]) = *image.get_pixel(x, y);
+            let low = ((x * 7919 + y * 104729) % 256) as u16;
+            Rgba([
+                r as u16 * 256 + low,
+                g as u16 * 256,
+                b as u16 * 256 + 255 - low,
+                a as u16 * 257,
+            ])
+        });
+
+        let carved = Carver::new()
+            .size(Vector2::new(52, 48))
+            .run(deep.clone())
+            .unwrap()
+            .image;
+        assert!(is_carved_from(&carved, &deep));
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("deep-image.png");
+        DepthImage::Rgba16(carved.clone()).save(&path).unwrap();
+        match DepthImage::open(&path).unwrap() {
+            DepthImage::Rgba16(loaded) => assert_eq!(loaded, carved),
+            _ => panic!("16-bit PNGs must load as 16-bit images"),
+        }
+
+        // Floating-point images aren't limited to `0..=1`.
+        let bright = Rgba32FImage::from_fn(64, 48, |x, y| {
+            let color = image.get_pixel(x, y).to_vec4() * (1. + x as f32 / 8.);
+            Rgba::from_vec4(color)
+        });
+        let carved = Carver::new()
+            .size(Vector2::new(52, 60))
+            .run(bright.clone())
+            .unwrap()
+            .image;
+        assert_eq!(carved.dimensions(), (52, 60));
+        assert!(carved.pixels().any(|pixel| pixel.0[0] > 1.));
+    }
+}
@@ -0,0 +1,260 @@
+use crate::carver::Axis;
+use crate::error::{CarveError, CarveResult, PathContext};
+use crate::index::SeamTracker;
+use crate::resize::{Pass, Phase, Plan};
+use crate::util::{
+    luma_to_rgba, vec4_to_rgba, CollectArrayError, ColorKernel, IterCollectArrayExt,
+    IterTryCollectExt, Kernel, KernelRect, WeightImage,
+};
+use crate::video::AnimationRecorder;
+use cgmath::{Vector2, Vector4, VectorSpace};
+use image::{Rgba, RgbaImage};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Saves the energy of the image, with the seam about to be carved highlighted, at the given steps
+/// of a resize. Step `0` is the energy of the image before anything is carved and step `i` is saved
+/// next to `path` as `{stem}-{i}.{ext}`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnergySteps {
+    pub path: PathBuf,
+    /// The steps at which the energy is saved, in decreasing order and without duplicates.
+    steps: Vec<u32>,
+}
+
+impl EnergySteps {
+    pub fn new(path: impl Into<PathBuf>, mut steps: Vec<u32>) -> Self {
+        // Sort for efficiency later on.
+        steps.sort_by(|a, b| a.cmp(b).reverse());
+        steps.dedup();
+
+        Self {
+            path: path.into(),
+            steps,
+        }
+    }
+
+    pub fn steps(&self) -> &[u32] {
+        &self.steps
+    }
+
+    /// The path of the energy saved at `step`.
+    pub fn path_at(&self, step: u32) -> PathBuf {
+        if step == 0 {
+            return self.path.clone();
+        }
+
+        let stem = self.path.file_stem().unwrap_or_default().to_string_lossy();
+        let ext = self.path.extension().unwrap_or_default().to_string_lossy();
+        self.path
+            .with_file_name(format!("{}-{}.{}", stem, step, ext))
+    }
+}
+
+impl FromStr for EnergySteps {
+    type Err = String;
+
+    /// Parses `path/to/image.png`, which only saves step `0`, or `path/to/image.png:1,2,3`.
+    fn from_str(arg: &str) -> Result<Self, Self::Err> {
+        const FORM_ERR: &str =
+            "Argument must take the form `path/to/image.png` or `path/to/image.png:1,2,3`. \
+             See help for more details.";
+
+        let (path, steps) = match arg.split(':').try_collect_array() {
+            Ok([path, right]) => {
+                let steps = right
+                    .split(',')
+                    .map(|part| part.parse::<u32>())
+                    .try_collect()
+                    .map_err(|_| FORM_ERR.to_string())?;
+
+                (path, steps)
+            }
+            Err(CollectArrayError::TooSmall(1)) => (arg, vec![0]),
+            Err(_) => return Err(FORM_ERR.to_string()),
+        };
+
+        let path = Path::new(path);
+        if path.file_name().is_none() || path.extension().is_none() {
+            return Err(FORM_ERR.to_string());
+        }
+
+        Ok(Self::new(path, steps))
+    }
+}
+
+/// An animated GIF of every carving pass. See [AnimationRecorder].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AnimationOptions {
+    pub path: PathBuf,
+    /// Only every `every`th pass is recorded.
+    pub every: usize,
+    /// Whether the energy of the image is shown next to it.
+    pub energy_panel: bool,
+}
+
+/// The debug views which [Carver](crate::Carver) saves alongside a resize.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Emissions {
+    pub energy: Option<EnergySteps>,
+    /// Paints the seams over the energy of the original image. The color of the seams determines
+    /// when they were carved, with green being earlier than red.
+    pub seams_on_energy: Option<PathBuf>,
+    /// Paints the seams over the original image.
+    pub seams_on_original: Option<PathBuf>,
+    pub animation: Option<AnimationOptions>,
+}
+
+impl Emissions {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Whether the seams of the resize must be tracked on the original image.
+    pub fn paints_seams(&self) -> bool {
+        self.seams_on_energy.is_some() || self.seams_on_original.is_some()
+    }
+
+    /// Starts recording the animation, if requested. `canvas` must fit the image at every step of
+    /// the resize.
+    pub fn start(&self, canvas: Vector2<i32>) -> CarveResult<Option<AnimationRecorder>> {
+        self.animation
+            .as_ref()
+            .map(|animation| {
+                AnimationRecorder::new(
+                    &animation.path.to_string_lossy(),
+                    canvas,
+                    animation.every,
+                    animation.energy_panel,
+                )
+            })
+            .transpose()
+    }
+
+    /// Saves the energy of the image before any seam is carved and checks that the requested steps
+    /// exist.
+    pub fn plan(&self, plan: &Plan) -> CarveResult<()> {
+        let energy = match &self.energy {
+            Some(energy) => energy,
+            None => return Ok(()),
+        };
+
+        // Handle 0 as a special case.
+        if energy.steps.last().copied() == Some(0) {
+            let axis = plan.steps.first().copied().unwrap_or(Axis::Vertical);
+            let path = energy.path_at(0);
+            luma_to_rgba(&plan.finder.energy.energy(plan.image, axis))
+                .save(&path)
+                .writing(&path)?;
+        }
+
+        // Validate indices
+        let i_max = plan.steps.len() as u32;
+        let bad_indices = energy
+            .steps
+            .iter()
+            .rev()
+            .copied()
+            .filter(|step| *step > i_max)
+            .collect::<Vec<_>>();
+
+        if !bad_indices.is_empty() {
+            return Err(CarveError::InvalidEmitIndex {
+                indices: bad_indices,
+                steps: i_max as usize,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Saves the energy of the pass and records it into the animation as requested.
+    pub fn pass(&self, pass: &Pass, recorder: Option<&mut AnimationRecorder>) -> CarveResult<()> {
+        if let (Some(energy), Pass::Carve(carve)) = (&self.energy, pass) {
+            match carve.phase {
+                Phase::Resize { step } if step > 0 && energy.steps.contains(&(step as u32)) => {
+                    // Update the image with the chosen seam
+                    let mut image = luma_to_rgba(&carve.energy());
+                    for pos in carve.seam().positions() {
+                        image.put(pos, Rgba([255, 0, 0, 255]));
+                    }
+
+                    let path = energy.path_at(step as u32);
+                    image.save(&path).writing(&path)?;
+                }
+                _ => {}
+            }
+        }
+
+        if let Some(recorder) = recorder {
+            recorder.record(pass.image(), || pass.energy(), pass.positions())?;
+        }
+
+        Ok(())
+    }
+
+    /// Paints the seams tracked over the course of a resize. `energy` is only called if the seams
+    /// are painted over the energy of the original image.
+    pub fn paint_seams<E>(
+        &self,
+        original: &dyn ColorKernel,
+        energy: E,
+        tracker: &SeamTracker,
+        seam_count: i32,
+    ) -> CarveResult<()>
+    where
+        E: FnOnce() -> WeightImage,
+    {
+        let save = |mut out: RgbaImage, path: &Path| {
+            // Paint the seams in the debug view
+            for (index, carved_at) in tracker.carved_at().pixels().iter().enumerate() {
+                if let Some(i) = carved_at {
+                    let color = vec4_to_rgba(
+                        Vector4::new(0., 1., 0., 1.)
+                            .lerp(Vector4::new(1., 0., 0., 1.), *i as f32 / seam_count as f32),
+                    );
+                    out.put(out.decode_pos(index), color);
+                }
+            }
+
+            out.save(path).writing(path)
+        };
+
+        if let Some(path) = &self.seams_on_energy {
+            save(luma_to_rgba(&energy()), path)?;
+        }
+
+        if let Some(path) = &self.seams_on_original {
+            let image = <RgbaImage as Kernel>::from_fn(original.size(), |pos| {
+                vec4_to_rgba(original.color(pos))
+            });
+            save(image, path)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn energy_steps_are_sorted_and_named_after_their_step() {
+        let energy = "out/energy.png:3,0,12,3".parse::<EnergySteps>().unwrap();
+        assert_eq!(energy.steps(), &[12, 3, 0]);
+        assert_eq!(energy.path_at(0), Path::new("out/energy.png"));
+        assert_eq!(energy.path_at(12), Path::new("out/energy-12.png"));
+
+        let energy = "energy.png".parse::<EnergySteps>().unwrap();
+        assert_eq!(energy.steps(), &[0]);
+
+        for arg in ["energy", "energy.png:", "energy.png:1:2", "energy.png:x"] {
+            assert!(
+                arg.parse::<EnergySteps>().is_err(),
+                "{:?} should not parse",
+                arg
+            );
+        }
+    }
+}
@@ -0,0 +1,1024 @@
+use crate::carver::Axis;
+use crate::util::{
+    par_pixels, par_weight_image, srgb_to_lab, srgb_to_oklab, ColorKernel, FmtDisplayIter, Kernel,
+    KernelRect, Timer, VecKernel, WeightImage,
+};
+use cgmath::{InnerSpace, Vector2, Vector4, Zero};
+use image::Luma;
+use rustfft::num_complex::Complex32;
+use rustfft::FftPlanner;
+use std::cmp::Ordering;
+use std::f32::consts::PI;
+use std::fmt::Debug;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// Determines the "energy" of every pixel in an image. Seams are carved through the pixels with the
+/// lowest energy first.
+pub trait EnergyFunction: Debug + Send + Sync {
+    /// Computes the energy of the pixel at `pos` for seams running along `axis`.
+    fn energy_at(&self, target: &dyn ColorKernel, axis: Axis, pos: Vector2<i32>) -> f32;
+
+    /// Computes the energy of every pixel in the image for seams running along `axis`. Rows are
+    /// computed in parallel.
+    fn energy(&self, target: &dyn ColorKernel, axis: Axis) -> WeightImage {
+        let _timer = Timer::start("energy");
+        par_weight_image(target.size(), |pos| self.energy_at(target, axis, pos))
+    }
+
+    /// The distance (in both directions) up to which the pixels of the image can affect the energy
+    /// of a pixel. This is used to only recompute the pixels next to a seam once it is carved out.
+    /// `None` indicates that every pixel of the image can affect every other pixel.
+    fn radius(&self) -> Option<i32> {
+        None
+    }
+}
+
+/// The names accepted by [energy_from_name].
+pub const ENERGY_NAMES: &[&str] = &[
+    "derivative",
+    "sobel",
+    "scharr",
+    "l1",
+    "entropy",
+    "hog",
+    "spectral-residual",
+    "center-surround",
+];
+
+/// The names of the saliency energy functions, which can also be blended into other energy
+/// functions with [Blend].
+pub const SALIENCY_NAMES: &[&str] = &["spectral-residual", "center-surround"];
+
+/// A saliency energy blended into another energy function. See [SALIENCY_NAMES]. Saliencies are
+/// parsed from `NAME:WEIGHT`, which checks both the name and the weight.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Saliency {
+    name: String,
+    weight: f32,
+}
+
+impl Saliency {
+    /// One of [SALIENCY_NAMES].
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The weight of the saliency in the blend, between `0` and `1`.
+    pub fn weight(&self) -> f32 {
+        self.weight
+    }
+
+    /// Blends the saliency into `energy`, giving every pixel an energy of
+    /// `(1 - weight) * energy + weight * saliency`.
+    pub fn blend(&self, energy: Arc<dyn EnergyFunction>, radius: Option<i32>) -> Blend {
+        // Parsing only accepts the names of saliency energy functions.
+        let saliency = energy_from_name(&self.name, radius).unwrap();
+        Blend {
+            parts: vec![(energy, 1. - self.weight), (saliency, self.weight)],
+        }
+    }
+}
+
+impl FromStr for Saliency {
+    type Err = String;
+
+    fn from_str(arg: &str) -> Result<Self, Self::Err> {
+        const FORM_ERR: &str = "Argument must take the form `NAME:WEIGHT` (e.g. \
+                                `center-surround:0.5`) where `WEIGHT` lies between 0 and 1.";
+
+        let (name, weight) = arg.split_once(':').ok_or_else(|| FORM_ERR.to_string())?;
+        if !SALIENCY_NAMES.contains(&name) {
+            return Err(format!(
+                "Unknown saliency `{}`. Expected one of {}.",
+                name,
+                FmtDisplayIter {
+                    iter: SALIENCY_NAMES.iter().map(|name| format!("`{}`", name)),
+                    sep: ", "
+                }
+            ));
+        }
+
+        match weight.parse::<f32>() {
+            Ok(weight) if (0.0..=1.0).contains(&weight) => Ok(Self {
+                name: name.to_string(),
+                weight,
+            }),
+            _ => Err(FORM_ERR.to_string()),
+        }
+    }
+}
+
+/// Constructs one of the built-in energy functions from its name. `radius` configures the window of
+/// the windowed energy functions and falls back to their default when `None`.
+pub fn energy_from_name(name: &str, radius: Option<i32>) -> Option<Arc<dyn EnergyFunction>> {
+    Some(match name {
+        "derivative" => Arc::new(SeamDerivative),
+        "sobel" => Arc::new(Sobel),
+        "scharr" => Arc::new(Scharr),
+        "l1" => Arc::new(L1Gradient),
+        "entropy" => Arc::new(Entropy {
+            radius: radius.unwrap_or(Entropy::DEFAULT_RADIUS),
+        }),
+        "hog" => Arc::new(Hog {
+            radius: radius.unwrap_or(Hog::DEFAULT_RADIUS),
+        }),
+        "spectral-residual" => Arc::new(SpectralResidual),
+        "center-surround" => Arc::new(CenterSurround {
+            radius: radius.unwrap_or(CenterSurround::DEFAULT_RADIUS),
+        }),
+        _ => return None,
+    })
+}
+
+// === Sampling utilities === //
+
+/// Reads a pixel, clamping `pos` to the image's bounds.
+fn sample(target: &dyn ColorKernel, pos: Vector2<i32>) -> Vector4<f32> {
+    target.color(clamp_pos(target, pos))
+}
+
+fn clamp_pos(target: &dyn ColorKernel, pos: Vector2<i32>) -> Vector2<i32> {
+    let size = target.size();
+    Vector2::new(pos.x.clamp(0, size.x - 1), pos.y.clamp(0, size.y - 1))
+}
+
+fn luma(color: Vector4<f32>) -> f32 {
+    0.299 * color.x + 0.587 * color.y + 0.114 * color.z
+}
+
+fn l1_norm(vec: Vector4<f32>) -> f32 {
+    vec.x.abs() + vec.y.abs() + vec.z.abs() + vec.w.abs()
+}
+
+/// Convolves the 3x3 neighborhood around `pos` with a derivative kernel whose smoothing profile is
+/// `profile` in both directions, returning the horizontal and vertical derivatives.
+fn convolve_3x3(
+    target: &dyn ColorKernel,
+    pos: Vector2<i32>,
+    profile: [f32; 3],
+) -> (Vector4<f32>, Vector4<f32>) {
+    let mut dx = Vector4::zero();
+    let mut dy = Vector4::zero();
+    for (i, weight) in profile.iter().enumerate() {
+        let offset = i as i32 - 1;
+        dx += (sample(target, pos + Vector2::new(1, offset))
+            - sample(target, pos + Vector2::new(-1, offset)))
+            * *weight;
+        dy += (sample(target, pos + Vector2::new(offset, 1))
+            - sample(target, pos + Vector2::new(offset, -1)))
+            * *weight;
+    }
+
+    // Normalize the profile so every kernel is on the same scale as a simple central difference.
+    let norm = profile.iter().sum::<f32>();
+    (dx / norm, dy / norm)
+}
+
+// === Energy functions === //
+
+/// The carver's original energy function: the magnitude of the difference between the two pixels
+/// on either side of the pixel across the seam. Pixels on the edges of the image are given the
+/// maximum energy so that seams never remove them.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct SeamDerivative;
+
+impl EnergyFunction for SeamDerivative {
+    fn energy_at(&self, target: &dyn ColorKernel, axis: Axis, pos: Vector2<i32>) -> f32 {
+        let left = target.try_color(pos - axis.unit());
+        let right = target.try_color(pos + axis.unit());
+        match (left, right) {
+            (Some(left), Some(right)) => (right - left).magnitude(),
+            _ => f32::MAX,
+        }
+    }
+
+    fn radius(&self) -> Option<i32> {
+        Some(1)
+    }
+}
+
+/// The magnitude of the gradient computed by the 3x3 Sobel operator.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Sobel;
+
+impl EnergyFunction for Sobel {
+    fn energy_at(&self, target: &dyn ColorKernel, _axis: Axis, pos: Vector2<i32>) -> f32 {
+        let (dx, dy) = convolve_3x3(target, pos, [1., 2., 1.]);
+        (dx.magnitude2() + dy.magnitude2()).sqrt()
+    }
+
+    fn radius(&self) -> Option<i32> {
+        Some(1)
+    }
+}
+
+/// The magnitude of the gradient computed by the 3x3 Scharr operator, which is more rotationally
+/// symmetric than the Sobel operator.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Scharr;
+
+impl EnergyFunction for Scharr {
+    fn energy_at(&self, target: &dyn ColorKernel, _axis: Axis, pos: Vector2<i32>) -> f32 {
+        let (dx, dy) = convolve_3x3(target, pos, [3., 10., 3.]);
+        (dx.magnitude2() + dy.magnitude2()).sqrt()
+    }
+
+    fn radius(&self) -> Option<i32> {
+        Some(1)
+    }
+}
+
+/// The L1 norm of the gradient computed using central differences (Avidan & Shamir's `e1`).
+#[derive(Debug, Copy, Clone, Default)]
+pub struct L1Gradient;
+
+impl EnergyFunction for L1Gradient {
+    fn energy_at(&self, target: &dyn ColorKernel, _axis: Axis, pos: Vector2<i32>) -> f32 {
+        let dx =
+            sample(target, pos + Vector2::new(1, 0)) - sample(target, pos - Vector2::new(1, 0));
+        let dy =
+            sample(target, pos + Vector2::new(0, 1)) - sample(target, pos - Vector2::new(0, 1));
+        l1_norm(dx) + l1_norm(dy)
+    }
+
+    fn radius(&self) -> Option<i32> {
+        Some(1)
+    }
+}
+
+/// The entropy of the brightness histogram of the `(2 * radius + 1)²` window around each pixel.
+/// Textured regions have high entropy while flat regions have none.
+#[derive(Debug, Copy, Clone)]
+pub struct Entropy {
+    pub radius: i32,
+}
+
+impl Entropy {
+    pub const DEFAULT_RADIUS: i32 = 4;
+    pub const BINS: usize = 16;
+}
+
+impl EnergyFunction for Entropy {
+    fn energy_at(&self, target: &dyn ColorKernel, _axis: Axis, pos: Vector2<i32>) -> f32 {
+        let mut histogram = [0u32; Entropy::BINS];
+        for y in -self.radius..=self.radius {
+            for x in -self.radius..=self.radius {
+                let luma = luma(sample(target, pos + Vector2::new(x, y)));
+                let bin = ((luma * Entropy::BINS as f32) as usize).min(Entropy::BINS - 1);
+                histogram[bin] += 1;
+            }
+        }
+
+        let total = ((2 * self.radius + 1) * (2 * self.radius + 1)) as f32;
+        histogram
+            .iter()
+            .filter(|count| **count > 0)
+            .map(|count| {
+                let p = *count as f32 / total;
+                -p * p.log2()
+            })
+            .sum()
+    }
+
+    fn radius(&self) -> Option<i32> {
+        Some(self.radius)
+    }
+}
+
+/// Avidan & Shamir's histogram of oriented gradients energy: the L1 gradient magnitude divided by
+/// the largest bin of the orientation histogram of the `(2 * radius + 1)²` window around each
+/// pixel. This lowers the energy of strongly textured regions which would otherwise attract every
+/// seam away from them.
+#[derive(Debug, Copy, Clone)]
+pub struct Hog {
+    pub radius: i32,
+}
+
+impl Hog {
+    pub const DEFAULT_RADIUS: i32 = 5;
+    pub const BINS: usize = 8;
+
+    /// Computes the brightness gradient magnitude and orientation bin of a single pixel.
+    fn gradient(target: &dyn ColorKernel, pos: Vector2<i32>) -> (f32, usize) {
+        let dx = luma(sample(target, pos + Vector2::new(1, 0)))
+            - luma(sample(target, pos - Vector2::new(1, 0)));
+        let dy = luma(sample(target, pos + Vector2::new(0, 1)))
+            - luma(sample(target, pos - Vector2::new(0, 1)));
+
+        // Orientations are unsigned so they wrap around every half turn.
+        let angle = dy.atan2(dx).rem_euclid(PI);
+        let bin = ((angle / PI * Hog::BINS as f32) as usize).min(Hog::BINS - 1);
+        ((dx * dx + dy * dy).sqrt(), bin)
+    }
+
+    /// Computes the energy of the pixel at `pos` given a function producing the gradient of every
+    /// in-bounds pixel.
+    fn energy_with<F>(&self, target: &dyn ColorKernel, pos: Vector2<i32>, gradient: F) -> f32
+    where
+        F: Fn(Vector2<i32>) -> (f32, usize),
+    {
+        let mut histogram = [0f32; Hog::BINS];
+        for y in -self.radius..=self.radius {
+            for x in -self.radius..=self.radius {
+                let (magnitude, bin) = gradient(clamp_pos(target, pos + Vector2::new(x, y)));
+                histogram[bin] += magnitude;
+            }
+        }
+
+        let max_bin = histogram.iter().copied().fold(0., f32::max);
+        L1Gradient.energy_at(target, Axis::Vertical, pos) / max_bin.max(f32::EPSILON)
+    }
+}
+
+impl EnergyFunction for Hog {
+    fn energy_at(&self, target: &dyn ColorKernel, _axis: Axis, pos: Vector2<i32>) -> f32 {
+        self.energy_with(target, pos, |pos| Hog::gradient(target, pos))
+    }
+
+    fn energy(&self, target: &dyn ColorKernel, _axis: Axis) -> WeightImage {
+        let _timer = Timer::start("energy");
+
+        // Every gradient is shared by an entire window of pixels so we compute them ahead of time.
+        let gradients =
+            VecKernel::<(f32, usize)>::par_from_fn(target.size(), |pos| Hog::gradient(target, pos));
+
+        par_weight_image(target.size(), |pos| {
+            self.energy_with(target, pos, |pos| *gradients.get(pos))
+        })
+    }
+
+    fn radius(&self) -> Option<i32> {
+        // The window's gradients reach one pixel further than the window itself.
+        Some(self.radius + 1)
+    }
+}
+
+// === Color spaces === //
+
+/// The color space in which [InColorSpace] compares neighboring pixels.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ColorSpace {
+    /// Gamma-encoded sRGB including alpha, which is what every energy function sees by default.
+    Rgba,
+    /// Gamma-encoded sRGB without alpha.
+    Rgb,
+    /// Only the luma of every pixel.
+    Luma,
+    /// CIELAB, divided by `100` so that it is on the same scale as sRGB. Distances are the CIE76
+    /// ΔE with lightness differences multiplied by `lightness_weight`.
+    Lab { lightness_weight: f32 },
+    /// Oklab, whose distances are more perceptually uniform than CIELAB's. Lightness differences
+    /// are multiplied by `lightness_weight`.
+    Oklab { lightness_weight: f32 },
+}
+
+/// The names accepted by [ColorSpace::from_name].
+pub const COLOR_SPACE_NAMES: &[&str] = &["rgba", "rgb", "luma", "lab", "oklab"];
+
+impl ColorSpace {
+    /// Constructs a color space from its name. `lightness_weight` only applies to `lab` and
+    /// `oklab`.
+    pub fn from_name(name: &str, lightness_weight: f32) -> Option<Self> {
+        Some(match name {
+            "rgba" => ColorSpace::Rgba,
+            "rgb" => ColorSpace::Rgb,
+            "luma" => ColorSpace::Luma,
+            "lab" => ColorSpace::Lab { lightness_weight },
+            "oklab" => ColorSpace::Oklab { lightness_weight },
+            _ => return None,
+        })
+    }
+
+    /// Converts a color from the image into this color space. The Euclidean distance between two
+    /// converted colors is their distance in this color space.
+    pub fn convert(self, color: Vector4<f32>) -> Vector4<f32> {
+        match self {
+            ColorSpace::Rgba => color,
+            ColorSpace::Rgb => color.truncate().extend(0.),
+            ColorSpace::Luma => Vector4::new(luma(color), 0., 0., 0.),
+            ColorSpace::Lab { lightness_weight } => {
+                let lab = srgb_to_lab(color.truncate()) / 100.;
+                Vector4::new(lab.x * lightness_weight, lab.y, lab.z, 0.)
+            }
+            ColorSpace::Oklab { lightness_weight } => {
+                let lab = srgb_to_oklab(color.truncate());
+                Vector4::new(lab.x * lightness_weight, lab.y, lab.z, 0.)
+            }
+        }
+    }
+}
+
+/// Runs another energy function on the colors of the image converted into a [ColorSpace]. This is
+/// meant for the gradient energy functions, which measure the distance between neighboring pixels.
+#[derive(Debug, Clone)]
+pub struct InColorSpace {
+    pub energy: Arc<dyn EnergyFunction>,
+    pub space: ColorSpace,
+}
+
+/// A view of an image whose colors are converted on the fly.
+struct Converted<'a> {
+    target: &'a dyn ColorKernel,
+    space: ColorSpace,
+}
+
+impl KernelRect for Converted<'_> {
+    fn size(&self) -> Vector2<i32> {
+        self.target.size()
+    }
+}
+
+impl ColorKernel for Converted<'_> {
+    fn color(&self, pos: Vector2<i32>) -> Vector4<f32> {
+        self.space.convert(self.target.color(pos))
+    }
+}
+
+/// An image whose colors have all been converted ahead of time.
+struct Precomputed(VecKernel<[f32; 4]>);
+
+impl KernelRect for Precomputed {
+    fn size(&self) -> Vector2<i32> {
+        self.0.size()
+    }
+}
+
+impl ColorKernel for Precomputed {
+    fn color(&self, pos: Vector2<i32>) -> Vector4<f32> {
+        Vector4::from(*self.0.get(pos))
+    }
+}
+
+impl EnergyFunction for InColorSpace {
+    fn energy_at(&self, target: &dyn ColorKernel, axis: Axis, pos: Vector2<i32>) -> f32 {
+        let target = Converted {
+            target,
+            space: self.space,
+        };
+        self.energy.energy_at(&target, axis, pos)
+    }
+
+    fn energy(&self, target: &dyn ColorKernel, axis: Axis) -> WeightImage {
+        // Every pixel is read by its neighbors too so we only convert each of them once.
+        let converted = Precomputed(VecKernel::par_from_fn(target.size(), |pos| {
+            self.space.convert(target.color(pos)).into()
+        }));
+        self.energy.energy(&converted, axis)
+    }
+
+    fn radius(&self) -> Option<i32> {
+        self.energy.radius()
+    }
+}
+
+// === Saliency === //
+
+/// Hou & Zhang's spectral residual saliency. The brightness of the image is shrunk down to a
+/// `SIZE²` grid whose log amplitude spectrum is compared with its local average. Whatever the
+/// spectrum has beyond its average is what makes the image stand out, so transforming only that
+/// residual back (with the original phase) highlights the salient regions of the image. The
+/// saliency is normalized so that the most salient pixel has an energy of `1`.
+///
+/// Because the spectrum depends on every pixel of the image, so does the energy of each pixel.
+/// [EnergyFunction::energy_at] computes the saliency of the whole image and should be avoided.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct SpectralResidual;
+
+impl SpectralResidual {
+    pub const SIZE: usize = 64;
+
+    /// Computes the saliency of the `SIZE²` grid.
+    fn saliency(target: &dyn ColorKernel) -> WeightImage {
+        let n = Self::SIZE;
+        let size = target.size();
+
+        // Shrink the image down by averaging the pixels covered by every cell.
+        let bounds = |cell: usize, len: i32| {
+            let start = cell as i32 * len / n as i32;
+            let end = ((cell + 1) as i32 * len / n as i32).max(start + 1);
+            start.min(len - 1)..end.min(len)
+        };
+        let mut spectrum = par_pixels(Vector2::new(n as i32, n as i32), |cell| {
+            let (xs, ys) = (
+                bounds(cell.x as usize, size.x),
+                bounds(cell.y as usize, size.y),
+            );
+            let mut sum = 0.;
+            for y in ys.clone() {
+                for x in xs.clone() {
+                    sum += luma(target.color(Vector2::new(x, y)));
+                }
+            }
+            Complex32::new(sum / (xs.len() * ys.len()) as f32, 0.)
+        });
+        fft_2d(&mut spectrum, n, false);
+
+        // The spectral residual is the difference between the log amplitude and its 3x3 average.
+        // The spectrum is periodic so the average wraps around its edges.
+        let log_amplitude = spectrum
+            .iter()
+            .map(|c| (c.norm() + f32::EPSILON).ln())
+            .collect::<Vec<_>>();
+        let mut residual = spectrum
+            .iter()
+            .enumerate()
+            .map(|(i, c)| {
+                let (x, y) = (i % n, i / n);
+                let mut average = 0.;
+                for dy in [n - 1, 0, 1] {
+                    for dx in [n - 1, 0, 1] {
+                        average += log_amplitude[(x + dx) % n + (y + dy) % n * n];
+                    }
+                }
+                let amplitude = (log_amplitude[i] - average / 9.).exp();
+                c * (amplitude / (c.norm() + f32::EPSILON))
+            })
+            .collect::<Vec<_>>();
+        fft_2d(&mut residual, n, true);
+
+        let saliency = WeightImage::from_raw(
+            n as u32,
+            n as u32,
+            residual.iter().map(|c| c.norm() * c.norm()).collect(),
+        )
+        .unwrap();
+        EnergyFilter::Blur { radius: 5 }.apply(&saliency)
+    }
+}
+
+impl EnergyFunction for SpectralResidual {
+    fn energy_at(&self, target: &dyn ColorKernel, axis: Axis, pos: Vector2<i32>) -> f32 {
+        self.energy(target, axis).get(pos).0[0]
+    }
+
+    fn energy(&self, target: &dyn ColorKernel, _axis: Axis) -> WeightImage {
+        let _timer = Timer::start("energy");
+        let saliency = Self::saliency(target);
+        let max = saliency.pixels().map(|p| p.0[0]).fold(0., f32::max);
+        let scale = if max > 0. { 1. / max } else { 0. };
+
+        // Scale the grid back up to the size of the image with bilinear interpolation.
+        let size = target.size();
+        let last = Self::SIZE as f32 - 1.;
+        par_weight_image(size, |pos| {
+            let grid = |pos: i32, len: i32| {
+                ((pos as f32 + 0.5) * Self::SIZE as f32 / len as f32 - 0.5).clamp(0., last)
+            };
+            let (x, y) = (grid(pos.x, size.x), grid(pos.y, size.y));
+            let (x0, y0) = (x.floor(), y.floor());
+            let (x1, y1) = ((x0 + 1.).min(last), (y0 + 1.).min(last));
+            let at = |x: f32, y: f32| saliency.get(Vector2::new(x as i32, y as i32)).0[0];
+
+            let top = at(x0, y0) + (at(x1, y0) - at(x0, y0)) * (x - x0);
+            let bottom = at(x0, y1) + (at(x1, y1) - at(x0, y1)) * (x - x0);
+            (top + (bottom - top) * (y - y0)) * scale
+        })
+    }
+}
+
+/// Center-surround contrast saliency: the CIELAB distance between the color of each pixel and the
+/// average color of the windows around it, averaged over windows of `radius / 4`, `radius / 2` and
+/// `radius`. Pixels which differ from their surroundings are salient even when they aren't
+/// textured, such as a face in front of a busy background. Distances are divided by `100` (the
+/// range of `L`) so that they are on the same scale as the other energy functions.
+///
+/// Recomputing the windows around every carved seam costs far more than recomputing every window
+/// of the image with a summed-area table so the energy is always recomputed as a whole.
+#[derive(Debug, Copy, Clone)]
+pub struct CenterSurround {
+    pub radius: i32,
+}
+
+impl CenterSurround {
+    pub const DEFAULT_RADIUS: i32 = 16;
+
+    fn radii(&self) -> [i32; 3] {
+        [self.radius / 4, self.radius / 2, self.radius].map(|radius| radius.max(1))
+    }
+
+    /// Computes the contrast of the pixel at `pos` given a function summing up the colors of the
+    /// window of a given radius around it. Surroundings are averaged before being converted.
+    fn contrast_with<F>(&self, target: &dyn ColorKernel, pos: Vector2<i32>, window_sum: F) -> f32
+    where
+        F: Fn(i32) -> Vector4<f64>,
+    {
+        let lab = |color: Vector4<f32>| srgb_to_lab(color.truncate());
+        let center = lab(sample(target, pos));
+        let contrast = self
+            .radii()
+            .iter()
+            .map(|&radius| {
+                let area = ((2 * radius + 1) * (2 * radius + 1)) as f64;
+                let mean = (window_sum(radius) / area).cast::<f32>().unwrap();
+                (lab(mean) - center).magnitude()
+            })
+            .sum::<f32>();
+        contrast / 3. / 100.
+    }
+}
+
+impl EnergyFunction for CenterSurround {
+    fn energy_at(&self, target: &dyn ColorKernel, _axis: Axis, pos: Vector2<i32>) -> f32 {
+        self.contrast_with(target, pos, |radius| {
+            let mut sum = Vector4::zero();
+            for y in -radius..=radius {
+                for x in -radius..=radius {
+                    sum += sample(target, pos + Vector2::new(x, y))
+                        .cast::<f64>()
+                        .unwrap();
+                }
+            }
+            sum
+        })
+    }
+
+    fn energy(&self, target: &dyn ColorKernel, _axis: Axis) -> WeightImage {
+        let _timer = Timer::start("energy");
+
+        // `table[(x, y)]` is the sum of the colors above and to the left of `(x, y)` in the image
+        // padded by `reach` clamped pixels on every side.
+        let reach = self.radius.max(1);
+        let size = target.size();
+        let padded = size + Vector2::new(2 * reach, 2 * reach);
+        let width = padded.x as usize + 1;
+        let mut table = vec![Vector4::<f64>::zero(); width * (padded.y as usize + 1)];
+        for y in 0..padded.y as usize {
+            let mut row = Vector4::zero();
+            for x in 0..padded.x as usize {
+                let pos = Vector2::new(x as i32 - reach, y as i32 - reach);
+                row += sample(target, pos).cast::<f64>().unwrap();
+                table[(x + 1) + (y + 1) * width] = table[(x + 1) + y * width] + row;
+            }
+        }
+
+        par_weight_image(size, |pos| {
+            self.contrast_with(target, pos, |radius| {
+                let at =
+                    |x: i32, y: i32| table[(x + reach) as usize + (y + reach) as usize * width];
+                let (min, max) = (pos.x - radius, pos.x + radius + 1);
+                let (top, bottom) = (pos.y - radius, pos.y + radius + 1);
+                at(max, bottom) - at(min, bottom) - at(max, top) + at(min, top)
+            })
+        })
+    }
+}
+
+/// A weighted sum of several energy functions, e.g. a gradient energy blended with a saliency
+/// energy.
+#[derive(Debug, Clone)]
+pub struct Blend {
+    pub parts: Vec<(Arc<dyn EnergyFunction>, f32)>,
+}
+
+impl EnergyFunction for Blend {
+    fn energy_at(&self, target: &dyn ColorKernel, axis: Axis, pos: Vector2<i32>) -> f32 {
+        self.parts
+            .iter()
+            .map(|(energy, weight)| energy.energy_at(target, axis, pos) * weight)
+            .sum()
+    }
+
+    fn energy(&self, target: &dyn ColorKernel, axis: Axis) -> WeightImage {
+        // Global energy functions are much cheaper to compute all at once.
+        let size = target.size();
+        let mut blended = WeightImage::new(size.x as u32, size.y as u32);
+        for (energy, weight) in &self.parts {
+            let energy = energy.energy(target, axis);
+            for (blended, energy) in blended.pixels_mut().zip(energy.pixels()) {
+                blended.0[0] += energy.0[0] * weight;
+            }
+        }
+        blended
+    }
+
+    fn radius(&self) -> Option<i32> {
+        self.parts
+            .iter()
+            .map(|(energy, _)| energy.radius())
+            .try_fold(0, |max, radius| Some(max.max(radius?)))
+    }
+}
+
+/// Transforms a row-major `n²` grid by transforming its rows and then its columns. The inverse
+/// transform is scaled by `1 / n²`.
+fn fft_2d(data: &mut [Complex32], n: usize, inverse: bool) {
+    let mut planner = FftPlanner::new();
+    let fft = if inverse {
+        planner.plan_fft_inverse(n)
+    } else {
+        planner.plan_fft_forward(n)
+    };
+
+    // Transform the rows, then the rows of the transposed grid.
+    let transpose = |data: &mut [Complex32]| {
+        for y in 0..n {
+            for x in y + 1..n {
+                data.swap(x + y * n, y + x * n);
+            }
+        }
+    };
+    fft.process(data);
+    transpose(data);
+    fft.process(data);
+    transpose(data);
+
+    if inverse {
+        let scale = 1. / (n * n) as f32;
+        for c in data {
+            *c *= scale;
+        }
+    }
+}
+
+// === Energy filters === //
+
+/// A stage applied to the energy of every pixel once it has been computed. Filtering the energy
+/// rather than the image changes which seams are carved without blurring the image itself.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EnergyFilter {
+    /// Blurs the energy with a Gaussian kernel whose standard deviation is half of its radius.
+    /// This thickens edges so that seams stop slipping through the gaps between them.
+    Blur { radius: i32 },
+    /// Replaces the energy of every pixel by the highest energy within its radius.
+    Dilate { radius: i32 },
+    /// Replaces the energy of every pixel by the median energy within its radius, which removes
+    /// isolated specks of noise.
+    Median { radius: i32 },
+}
+
+/// The names of the stages accepted by [EnergyFilter]'s [FromStr] implementation.
+pub const ENERGY_FILTER_NAMES: &[&str] = &["blur", "dilate", "median"];
+
+impl EnergyFilter {
+    /// The distance up to which the energy of a pixel affects the filtered energy of others.
+    pub fn radius(&self) -> i32 {
+        match *self {
+            EnergyFilter::Blur { radius }
+            | EnergyFilter::Dilate { radius }
+            | EnergyFilter::Median { radius } => radius,
+        }
+    }
+
+    /// Filters `energy`. Pixels beyond the edges of `energy` take the value of the closest edge
+    /// pixel.
+    pub fn apply(&self, energy: &WeightImage) -> WeightImage {
+        let _timer = Timer::start("energy_filter");
+        self.apply_with(energy, |size, handler| par_weight_image(size, handler))
+    }
+
+    fn apply_with<B>(&self, energy: &WeightImage, build: B) -> WeightImage
+    where
+        B: Fn(Vector2<i32>, &(dyn Fn(Vector2<i32>) -> f32 + Sync)) -> WeightImage,
+    {
+        let size = energy.size();
+        let sample = |energy: &WeightImage, pos: Vector2<i32>| {
+            let pos = Vector2::new(pos.x.clamp(0, size.x - 1), pos.y.clamp(0, size.y - 1));
+            energy.get(pos).0[0]
+        };
+
+        match *self {
+            EnergyFilter::Blur { radius } => {
+                let sigma = (radius as f32 / 2.).max(f32::EPSILON);
+                let weights = (-radius..=radius)
+                    .map(|offset| (-(offset * offset) as f32 / (2. * sigma * sigma)).exp())
+                    .collect::<Vec<_>>();
+                let total = weights.iter().sum::<f32>();
+
+                // The kernel is separable so we blur rows and then columns. Edge pixels carry the
+                // maximum energy, which must not overflow once summed up.
+                let blur = |energy: &WeightImage, unit: Vector2<i32>| {
+                    build(size, &|pos| {
+                        let sum = (-radius..=radius)
+                            .zip(&weights)
+                            .map(|(offset, weight)| sample(energy, pos + unit * offset) * weight)
+                            .sum::<f32>();
+                        (sum / total).min(f32::MAX)
+                    })
+                };
+                blur(&blur(energy, Vector2::new(1, 0)), Vector2::new(0, 1))
+            }
+            EnergyFilter::Dilate { radius } => {
+                let dilate = |energy: &WeightImage, unit: Vector2<i32>| {
+                    build(size, &|pos| {
+                        (-radius..=radius)
+                            .map(|offset| sample(energy, pos + unit * offset))
+                            .fold(f32::MIN, f32::max)
+                    })
+                };
+                dilate(&dilate(energy, Vector2::new(1, 0)), Vector2::new(0, 1))
+            }
+            EnergyFilter::Median { radius } => build(size, &|pos| {
+                let mut window = Vec::with_capacity(((2 * radius + 1) * (2 * radius + 1)) as usize);
+                for y in -radius..=radius {
+                    for x in -radius..=radius {
+                        window.push(sample(energy, pos + Vector2::new(x, y)));
+                    }
+                }
+                let middle = window.len() / 2;
+                *window
+                    .select_nth_unstable_by(middle, |a, b| {
+                        a.partial_cmp(b).unwrap_or(Ordering::Equal)
+                    })
+                    .1
+            }),
+        }
+    }
+}
+
+impl FromStr for EnergyFilter {
+    type Err = String;
+
+    fn from_str(arg: &str) -> Result<Self, Self::Err> {
+        const FORM_ERR: &str = "Filters must take the form `STAGE:RADIUS` (e.g. `blur:2`) where \
+                                `STAGE` is one of `blur`, `dilate` or `median`.";
+
+        let (stage, radius) = arg.split_once(':').ok_or_else(|| FORM_ERR.to_string())?;
+        let radius = match radius.parse::<i32>() {
+            Ok(radius) if radius >= 0 => radius,
+            _ => return Err("Filter radii must be non-negative integers.".to_string()),
+        };
+
+        match stage {
+            "blur" => Ok(EnergyFilter::Blur { radius }),
+            "dilate" => Ok(EnergyFilter::Dilate { radius }),
+            "median" => Ok(EnergyFilter::Median { radius }),
+            _ => Err(FORM_ERR.to_string()),
+        }
+    }
+}
+
+/// Runs the energy computed by another energy function through a sequence of [EnergyFilter]s.
+#[derive(Debug, Clone)]
+pub struct Filtered {
+    pub energy: Arc<dyn EnergyFunction>,
+    pub filters: Vec<EnergyFilter>,
+}
+
+impl Filtered {
+    /// The distance up to which the unfiltered energy of a pixel affects the filtered energy.
+    fn reach(&self) -> i32 {
+        self.filters.iter().map(EnergyFilter::radius).sum()
+    }
+}
+
+impl EnergyFunction for Filtered {
+    fn energy_at(&self, target: &dyn ColorKernel, axis: Axis, pos: Vector2<i32>) -> f32 {
+        // Filter the patch of the image which can affect this pixel. Intermediate values near the
+        // edges of the patch are wrong unless the patch touches the edge of the image but these
+        // never reach the center of the patch.
+        let reach = self.reach();
+        let size = target.size();
+        let min = Vector2::new((pos.x - reach).max(0), (pos.y - reach).max(0));
+        let max = Vector2::new(
+            (pos.x + reach).min(size.x - 1),
+            (pos.y + reach).min(size.y - 1),
+        );
+
+        let mut patch: WeightImage = Kernel::from_fn(max - min + Vector2::new(1, 1), |offset| {
+            Luma([self.energy.energy_at(target, axis, min + offset)])
+        });
+        for filter in &self.filters {
+            patch = filter.apply_with(&patch, |size, handler| {
+                Kernel::from_fn(size, |pos| Luma([handler(pos)]))
+            });
+        }
+        patch.get(pos - min).0[0]
+    }
+
+    fn energy(&self, target: &dyn ColorKernel, axis: Axis) -> WeightImage {
+        let mut energy = self.energy.energy(target, axis);
+        for filter in &self.filters {
+            energy = filter.apply(&energy);
+        }
+        energy
+    }
+
+    fn radius(&self) -> Option<i32> {
+        self.energy.radius().map(|radius| radius + self.reach())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::imageops::crop_imm;
+
+    #[test]
+    fn filtered_energy_at_matches_filtered_energy() {
+        let path = format!("{}/images/cat.png", env!("CARGO_MANIFEST_DIR"));
+        let image = image::open(path).unwrap().into_rgba8();
+        let image = crop_imm(&image, 200, 200, 24, 18).to_image();
+
+        let filtered = Filtered {
+            energy: Arc::new(Sobel),
+            filters: vec![
+                EnergyFilter::Median { radius: 1 },
+                EnergyFilter::Dilate { radius: 1 },
+                EnergyFilter::Blur { radius: 2 },
+            ],
+        };
+
+        let energy = filtered.energy(&image, Axis::Vertical);
+        for y in 0..image.height() as i32 {
+            for x in 0..image.width() as i32 {
+                let pos = Vector2::new(x, y);
+                let expected = energy.get(pos).0[0];
+                let actual = filtered.energy_at(&image, Axis::Vertical, pos);
+                assert!(
+                    (expected - actual).abs() <= expected.abs() * 1e-5,
+                    "{:?}",
+                    pos
+                );
+            }
+        }
+        assert_eq!("blur:3".parse(), Ok(EnergyFilter::Blur { radius: 3 }));
+        assert!("sharpen:3".parse::<EnergyFilter>().is_err());
+    }
+
+    #[test]
+    fn saliency_highlights_the_odd_one_out() {
+        // A red square on a grainy gray background.
+        let image = image::RgbaImage::from_fn(96, 64, |x, y| {
+            if (60..72).contains(&x) && (20..32).contains(&y) {
+                image::Rgba([200, 40, 40, 255])
+            } else {
+                let grain = (x * 7919 + y * 104729) % 31;
+                let gray = 113 + grain as u8;
+                image::Rgba([gray, gray, gray, 255])
+            }
+        });
+        let square = Vector2::new(65, 25);
+        let background = Vector2::new(10, 50);
+
+        let spectral = SpectralResidual.energy(&image, Axis::Vertical);
+        assert!(spectral.get(square).0[0] > 2. * spectral.get(background).0[0]);
+
+        let center_surround = CenterSurround { radius: 8 };
+        assert!(
+            center_surround.energy_at(&image, Axis::Vertical, square)
+                > center_surround.energy_at(&image, Axis::Vertical, background) + 0.1
+        );
+
+        // Blends weigh every part.
+        let blend = Blend {
+            parts: vec![(Arc::new(Sobel), 0.25), (Arc::new(center_surround), 0.75)],
+        };
+        let expected = 0.25 * Sobel.energy_at(&image, Axis::Vertical, square)
+            + 0.75 * center_surround.energy_at(&image, Axis::Vertical, square);
+        let blended = blend.energy(&image, Axis::Vertical).get(square).0[0];
+        assert!((blended - expected).abs() < 1e-5);
+        assert_eq!(blend.radius(), None);
+
+        let energy = center_surround.energy(&image, Axis::Vertical);
+        for pos in [square, background, Vector2::new(0, 0), Vector2::new(95, 63)] {
+            let expected = center_surround.energy_at(&image, Axis::Vertical, pos);
+            assert!((energy.get(pos).0[0] - expected).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn color_spaces_see_different_edges() {
+        // A reddish half next to a greenish half of (almost) the same luma, both fading out at
+        // the bottom.
+        let image = image::RgbaImage::from_fn(16, 16, |x, y| {
+            let alpha = if y < 8 { 255 } else { 64 };
+            if x < 8 {
+                image::Rgba([200, 100, 100, alpha])
+            } else {
+                image::Rgba([100, 150, 100, alpha])
+            }
+        });
+        let energy_at = |space, pos| {
+            let energy = InColorSpace {
+                energy: Arc::new(Sobel),
+                space,
+            };
+            let expected = energy.energy(&image, Axis::Vertical).get(pos).0[0];
+            let actual = energy.energy_at(&image, Axis::Vertical, pos);
+            assert!((expected - actual).abs() < 1e-5);
+            actual
+        };
+
+        let hue_edge = Vector2::new(8, 4);
+        let luma = energy_at(ColorSpace::Luma, hue_edge);
+        let lab = energy_at(
+            ColorSpace::Lab {
+                lightness_weight: 1.,
+            },
+            hue_edge,
+        );
+        let oklab = energy_at(
+            ColorSpace::Oklab {
+                lightness_weight: 1.,
+            },
+            hue_edge,
+        );
+        assert!(lab > 20. * luma, "{} {}", lab, luma);
+        assert!(oklab > 20. * luma, "{} {}", oklab, luma);
+
+        let alpha_edge = Vector2::new(4, 8);
+        assert_eq!(energy_at(ColorSpace::Rgb, alpha_edge), 0.);
+        assert!(energy_at(ColorSpace::Rgba, alpha_edge) > 0.);
+    }
+}
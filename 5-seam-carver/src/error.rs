@@ -0,0 +1,207 @@
+//! Error reporting built on the standard library's [Error] trait. Every failure of the library is
+//! a [CarveError], and [ErrorFormatExt] formats an error followed by the chain of its sources.
+
+use crate::util::FmtDisplayIter;
+use image::{ImageError, ImageResult};
+use std::error::Error;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub type CarveResult<T> = Result<T, CarveError>;
+
+/// Everything which can go wrong while carving an image. Every variant has its own exit code so
+/// that scripts can tell them apart.
+#[derive(Debug)]
+pub enum CarveError {
+    /// A file could not be read or written.
+    Io { path: PathBuf, source: io::Error },
+    /// A file was read but its contents could not be decoded.
+    Decode {
+        path: PathBuf,
+        source: Box<dyn Error + Send + Sync>,
+    },
+    /// An image could not be encoded into the requested format.
+    Encode {
+        path: PathBuf,
+        source: Box<dyn Error + Send + Sync>,
+    },
+    /// The requested size cannot be produced.
+    InvalidSize(String),
+    /// `--emit-sobel` asked for steps which the resize doesn't go through.
+    InvalidEmitIndex { indices: Vec<u32>, steps: usize },
+    /// The inputs are inconsistent with one another, e.g. a mask of the wrong size.
+    InvalidInput(String),
+    /// Some of the outputs of a batch failed. Their errors are reported in the batch's summary.
+    BatchFailed { failed: usize, total: usize },
+}
+
+impl CarveError {
+    /// The status with which the process exits because of this error. `1` is reserved for
+    /// command line parsing errors.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            CarveError::Io { .. } => 2,
+            CarveError::Decode { .. } => 3,
+            CarveError::Encode { .. } => 4,
+            CarveError::InvalidSize(_) => 5,
+            CarveError::InvalidEmitIndex { .. } => 6,
+            CarveError::InvalidInput(_) => 7,
+            CarveError::BatchFailed { .. } => 8,
+        }
+    }
+}
+
+impl Display for CarveError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            CarveError::Io { path, .. } => write!(f, "Failed to access {:?}", path),
+            CarveError::Decode { path, .. } => write!(f, "Failed to decode {:?}", path),
+            CarveError::Encode { path, .. } => write!(f, "Failed to encode {:?}", path),
+            CarveError::InvalidSize(message) | CarveError::InvalidInput(message) => {
+                write!(f, "{}", message)
+            }
+            CarveError::InvalidEmitIndex { indices, steps } => write!(
+                f,
+                "Specified invalid `--emit-sobel` emission indices: {} (there are only {} step{})",
+                FmtDisplayIter {
+                    iter: indices,
+                    sep: ", "
+                },
+                steps,
+                if *steps == 1 { "" } else { "s" }
+            ),
+            CarveError::BatchFailed { failed, total } => {
+                write!(f, "{} of {} batch outputs failed.", failed, total)
+            }
+        }
+    }
+}
+
+impl Error for CarveError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CarveError::Io { source, .. } => Some(source),
+            CarveError::Decode { source, .. } | CarveError::Encode { source, .. } => {
+                Some(source.as_ref())
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Attaches the path of the file being read or written to I/O and image errors.
+pub trait PathContext<T> {
+    fn reading(self, path: impl AsRef<Path>) -> CarveResult<T>;
+    fn writing(self, path: impl AsRef<Path>) -> CarveResult<T>;
+}
+
+impl<T> PathContext<T> for io::Result<T> {
+    fn reading(self, path: impl AsRef<Path>) -> CarveResult<T> {
+        self.map_err(|source| CarveError::Io {
+            path: path.as_ref().to_path_buf(),
+            source,
+        })
+    }
+
+    fn writing(self, path: impl AsRef<Path>) -> CarveResult<T> {
+        self.reading(path)
+    }
+}
+
+impl<T> PathContext<T> for ImageResult<T> {
+    fn reading(self, path: impl AsRef<Path>) -> CarveResult<T> {
+        let path = path.as_ref().to_path_buf();
+        self.map_err(|err| match err {
+            ImageError::IoError(source) => CarveError::Io { path, source },
+            err => CarveError::Decode {
+                path,
+                source: Box::new(err),
+            },
+        })
+    }
+
+    fn writing(self, path: impl AsRef<Path>) -> CarveResult<T> {
+        let path = path.as_ref().to_path_buf();
+        self.map_err(|err| match err {
+            ImageError::IoError(source) => CarveError::Io { path, source },
+            err => CarveError::Encode {
+                path,
+                source: Box::new(err),
+            },
+        })
+    }
+}
+
+impl<T> PathContext<T> for Result<T, png::DecodingError> {
+    fn reading(self, path: impl AsRef<Path>) -> CarveResult<T> {
+        let path = path.as_ref().to_path_buf();
+        self.map_err(|err| match err {
+            png::DecodingError::IoError(source) => CarveError::Io { path, source },
+            err => CarveError::Decode {
+                path,
+                source: Box::new(err),
+            },
+        })
+    }
+
+    fn writing(self, path: impl AsRef<Path>) -> CarveResult<T> {
+        self.reading(path)
+    }
+}
+
+impl<T> PathContext<T> for Result<T, png::EncodingError> {
+    fn reading(self, path: impl AsRef<Path>) -> CarveResult<T> {
+        self.writing(path)
+    }
+
+    fn writing(self, path: impl AsRef<Path>) -> CarveResult<T> {
+        let path = path.as_ref().to_path_buf();
+        self.map_err(|err| match err {
+            png::EncodingError::IoError(source) => CarveError::Io { path, source },
+            err => CarveError::Encode {
+                path,
+                source: Box::new(err),
+            },
+        })
+    }
+}
+
+/// Formats an error as `Error: {error}`, followed by every error in its [Error::source] chain.
+pub trait ErrorFormatExt {
+    fn format_error(&self) -> FormattedError<'_, Self>;
+}
+
+impl<T: ?Sized + Error> ErrorFormatExt for T {
+    fn format_error(&self) -> FormattedError<'_, Self> {
+        FormattedError { target: self }
+    }
+}
+
+pub struct FormattedError<'a, T: ?Sized> {
+    target: &'a T,
+}
+
+impl<T: ?Sized + Error> Display for FormattedError<'_, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        let target = self.target;
+
+        // Write context
+        writeln!(f, "Error: {}", target)?;
+
+        // Write cause chain
+        {
+            let mut cause_iter = target.source();
+            if cause_iter.is_some() {
+                writeln!(f, "\nCaused by:")?;
+            }
+
+            while let Some(cause) = cause_iter {
+                writeln!(f, "\t {}", cause)?;
+                cause_iter = cause.source();
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,374 @@
+use crate::carved::CarvedKernel;
+use crate::carver::{insert, Axis, LowestDerivative, Masks, SeamCache, SeamFinder, SeamMask};
+use crate::depth::{ColorPixel, DepthImage};
+use crate::error::{CarveError, CarveResult, PathContext};
+use crate::util::{ImageBufferVec, Kernel, KernelRect, Timer, VecKernel};
+use cgmath::Vector2;
+use image::{open, DynamicImage, ImageBuffer, Luma, Rgba, RgbaImage};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::Path;
+
+/// Tracks which pixel of the original image every pixel of a carved image came from, along with
+/// the index of the seam which removed (or inserted) every original pixel.
+#[derive(Debug, Clone)]
+pub struct SeamTracker {
+    map: CarvedKernel<VecKernel<usize>>,
+    carved_at: VecKernel<Option<i32>>,
+}
+
+impl SeamTracker {
+    pub fn new(size: Vector2<i32>) -> Self {
+        Self {
+            map: CarvedKernel::from_fn(size, |pos| size.encode_pos(pos)),
+            carved_at: VecKernel::new(size),
+        }
+    }
+
+    /// The index of the seam which removed or inserted every pixel of the original image.
+    pub fn carved_at(&self) -> &VecKernel<Option<i32>> {
+        &self.carved_at
+    }
+
+    /// Records that `seam`, the `i`th seam, is about to be carved out of the image.
+    pub fn carve(&mut self, seam: &LowestDerivative, i: i32) {
+        for seam_pos in seam.positions() {
+            let world_pos = *self.map.get(seam_pos);
+            let world_pos = self.carved_at.decode_pos(world_pos);
+            self.carved_at.put(world_pos, Some(i));
+        }
+
+        // Update the seam-space to original-space map
+        self.map.carve(seam.axis(), seam.iter());
+    }
+
+    /// Records that `seams`, starting with the `i`th seam, are about to be inserted into the image.
+    pub fn insert(&mut self, seams: &SeamMask, axis: Axis, count: i32, i: i32) {
+        for (index, seam_i) in seams.pixels().iter().enumerate() {
+            if let Some(seam_i) = seam_i {
+                let world_pos = *self.map.get(seams.decode_pos(index));
+                let world_pos = self.carved_at.decode_pos(world_pos);
+                self.carved_at.put(world_pos, Some(i + seam_i));
+            }
+        }
+
+        // Both copies of an inserted seam map back to the same original pixel.
+        self.map = insert(&self.map, axis, seams, count, |_, curr, _| [*curr, *curr]);
+    }
+}
+
+/// Records the step at which every pixel of an image is removed when carving it down to a single
+/// column, which lets [SeamIndex::apply] produce any narrower width without finding any seams.
+#[derive(Debug, Clone)]
+pub struct SeamIndex {
+    steps: VecKernel<u32>,
+}
+
+impl SeamIndex {
+    /// The magic bytes at the start of binary index files.
+    pub const MAGIC: &'static [u8; 8] = b"SEAMIDX\0";
+
+    /// Carves `image` down to a single column, recording the step at which every pixel is removed.
+    /// The pixels of the last column are recorded as being removed at the final step.
+    pub fn build<K>(image: &K, finder: &SeamFinder, masks: &Masks) -> Self
+    where
+        K: Kernel + Sync,
+        K::Pixel: ColorPixel,
+    {
+        let _timer = Timer::start("build_index");
+        let axis = Axis::Vertical;
+
+        let mut image = CarvedKernel::from(image.clone());
+        let mut masks = masks.clone();
+        let mut tracker = SeamTracker::new(image.size());
+        let mut cache = SeamCache::new(finder, &image, axis, &masks);
+        let width = image.size().x;
+
+        for i in 0..width - 1 {
+            let seam = cache.seam();
+            tracker.carve(seam, i);
+            image.carve(axis, seam.iter());
+            masks.carve(seam);
+            if i + 2 < width {
+                cache.carve(finder, &image, &masks);
+            }
+        }
+
+        let carved_at = tracker.carved_at();
+        Self {
+            steps: VecKernel::from_fn(carved_at.size(), |pos| {
+                carved_at.get(pos).unwrap_or(width - 1) as u32
+            }),
+        }
+    }
+
+    /// The step at which every pixel is removed.
+    pub fn steps(&self) -> &VecKernel<u32> {
+        &self.steps
+    }
+
+    /// Produces `image` carved down to `width` by keeping the pixels which haven't been removed yet.
+    pub fn apply<P: ColorPixel>(
+        &self,
+        image: &ImageBufferVec<P>,
+        width: i32,
+    ) -> CarveResult<ImageBufferVec<P>> {
+        let _timer = Timer::start("apply_index");
+        let size = self.steps.size();
+
+        if image.size() != size {
+            return Err(CarveError::InvalidInput(format!(
+                "The index must have the same dimensions as the input image. \
+                 (index is {}x{} but image is {}x{})",
+                size.x,
+                size.y,
+                image.width(),
+                image.height()
+            )));
+        }
+
+        if width <= 0 || width > size.x {
+            return Err(CarveError::InvalidSize(format!(
+                "Target width must be between 1 and {}. (wants resize from {} to {})",
+                size.x, size.x, width
+            )));
+        }
+
+        // Every row removes exactly one pixel per step so every row keeps exactly `width` pixels.
+        let removed = (size.x - width) as u32;
+        let mut pixels =
+            Vec::with_capacity(width as usize * size.y as usize * P::CHANNEL_COUNT as usize);
+        for (pixel, step) in image.pixels().zip(self.steps.pixels()) {
+            if *step >= removed {
+                pixels.extend_from_slice(pixel.channels());
+            }
+        }
+
+        Ok(ImageBufferVec::from_raw(width as u32, size.y as u32, pixels).unwrap())
+    }
+
+    /// Applies the index to an image at its own depth. See [SeamIndex::apply].
+    pub fn apply_depth(&self, image: &DepthImage, width: i32) -> CarveResult<DepthImage> {
+        Ok(match image {
+            DepthImage::Rgba8(image) => DepthImage::Rgba8(self.apply(image, width)?),
+            DepthImage::Rgba16(image) => DepthImage::Rgba16(self.apply(image, width)?),
+            DepthImage::Rgba32F(image) => DepthImage::Rgba32F(self.apply(image, width)?),
+        })
+    }
+
+    /// Saves the index as a 16-bit grayscale PNG (or a 32-bit RGBA PNG holding the big-endian steps
+    /// if the image is too wide) if `path` ends with `.png` or as a binary file otherwise.
+    pub fn save(&self, path: &str) -> CarveResult<()> {
+        let size = self.steps.size();
+        let steps = self.steps.pixels();
+        if is_png_path(path) {
+            if size.x <= u16::MAX as i32 + 1 {
+                let raw = steps.iter().map(|step| *step as u16).collect::<Vec<_>>();
+                ImageBuffer::<Luma<u16>, _>::from_raw(size.x as u32, size.y as u32, raw)
+                    .unwrap()
+                    .save(path)
+                    .writing(path)
+            } else {
+                let raw = steps.iter().flat_map(|step| step.to_be_bytes()).collect();
+                RgbaImage::from_raw(size.x as u32, size.y as u32, raw)
+                    .unwrap()
+                    .save(path)
+                    .writing(path)
+            }
+        } else {
+            File::create(path)
+                .and_then(|file| {
+                    let mut writer = BufWriter::new(file);
+                    writer.write_all(Self::MAGIC)?;
+                    writer.write_all(&(size.x as u32).to_le_bytes())?;
+                    writer.write_all(&(size.y as u32).to_le_bytes())?;
+                    for step in steps {
+                        writer.write_all(&step.to_le_bytes())?;
+                    }
+                    writer.flush()
+                })
+                .writing(path)
+        }
+    }
+
+    /// Loads an index saved by [SeamIndex::save].
+    pub fn load(path: &str) -> CarveResult<Self> {
+        let invalid = |message: &str| CarveError::Decode {
+            path: path.into(),
+            source: message.into(),
+        };
+
+        let steps = if is_png_path(path) {
+            match open(path).reading(path)? {
+                DynamicImage::ImageLuma16(image) => {
+                    VecKernel::from_fn(image.size(), |pos| image.get(pos).0[0] as u32)
+                }
+                DynamicImage::ImageRgba8(image) => VecKernel::from_fn(image.size(), |pos| {
+                    let Rgba(bytes) = *image.get(pos);
+                    u32::from_be_bytes(bytes)
+                }),
+                _ => return Err(invalid("not a 16-bit grayscale or 32-bit RGBA image")),
+            }
+        } else {
+            Self::read_binary(path)?
+        };
+
+        // Every row must remove exactly one pixel per step.
+        let size = steps.size();
+        let mut seen = vec![false; size.x as usize];
+        for y in 0..size.y {
+            seen.fill(false);
+            for x in 0..size.x {
+                let step = *steps.get(Vector2::new(x, y)) as usize;
+                if step >= seen.len() || std::mem::replace(&mut seen[step], true) {
+                    return Err(invalid("not a valid seam index"));
+                }
+            }
+        }
+
+        Ok(Self { steps })
+    }
+
+    fn read_binary(path: &str) -> CarveResult<VecKernel<u32>> {
+        let io_error = |err: std::io::Error| match err.kind() {
+            ErrorKind::InvalidData | ErrorKind::UnexpectedEof => CarveError::Decode {
+                path: path.into(),
+                source: err.into(),
+            },
+            _ => CarveError::Io {
+                path: path.into(),
+                source: err,
+            },
+        };
+
+        let file = File::open(path).map_err(io_error)?;
+        let file_len = file.metadata().map_err(io_error)?.len();
+        let mut reader = BufReader::new(file);
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic).map_err(io_error)?;
+        if magic != *Self::MAGIC {
+            return Err(io_error(std::io::Error::new(
+                ErrorKind::InvalidData,
+                "missing seam index header",
+            )));
+        }
+
+        let mut read_u32 = || -> std::io::Result<u32> {
+            let mut bytes = [0; 4];
+            reader.read_exact(&mut bytes)?;
+            Ok(u32::from_le_bytes(bytes))
+        };
+
+        // The header is trusted with nothing until it matches the length of the file, which keeps
+        // corrupt indices from triggering huge allocations.
+        let (width, height) = (read_u32().map_err(io_error)?, read_u32().map_err(io_error)?);
+        let header_len = Self::MAGIC.len() as u64 + 8;
+        let expected_len = (width as u64 * height as u64)
+            .checked_mul(4)
+            .and_then(|len| len.checked_add(header_len));
+        if width == 0
+            || height == 0
+            || width > i32::MAX as u32
+            || height > i32::MAX as u32
+            || expected_len != Some(file_len)
+        {
+            return Err(CarveError::InvalidInput(format!(
+                "The seam index {:?} claims to be {}x{} but holds {} bytes.",
+                path, width, height, file_len
+            )));
+        }
+
+        let size = Vector2::new(width as i32, height as i32);
+        let mut steps = Vec::with_capacity(size.dim());
+        for _ in 0..size.dim() {
+            steps.push(read_u32().map_err(io_error)?);
+        }
+
+        let mut steps = steps.into_iter();
+        Ok(VecKernel::from_fn(size, |_| steps.next().unwrap()))
+    }
+}
+
+fn is_png_path(path: &str) -> bool {
+    Path::new(path)
+        .extension()
+        .map_or(false, |ext| ext.eq_ignore_ascii_case("png"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::carver::carve;
+
+    #[test]
+    fn applied_indices_match_carving() {
+        let path = format!("{}/images/cat.png", env!("CARGO_MANIFEST_DIR"));
+        let image = image::open(path).unwrap().into_rgba8();
+        let image = image::imageops::thumbnail(&image, 80, 60);
+        let finder = SeamFinder::default();
+        let index = SeamIndex::build(&image, &finder, &Masks::default());
+
+        // Both file formats must round-trip.
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["seam-index.png", "seam-index.bin"] {
+            let path = dir.path().join(name);
+            let path = path.to_str().unwrap();
+            index.save(path).unwrap();
+            assert_eq!(
+                SeamIndex::load(path).unwrap().steps().pixels(),
+                index.steps().pixels()
+            );
+        }
+
+        let mut carved = image.clone();
+        let mut cache = SeamCache::new(&finder, &carved, Axis::Vertical, &Masks::default());
+        for width in (1..80).rev() {
+            let seam = cache.seam().iter().collect::<Vec<_>>();
+            carved = carve(&carved, Axis::Vertical, seam);
+            if width > 1 {
+                cache.carve(&finder, &carved, &Masks::default());
+            }
+            assert_eq!(index.apply(&image, width).unwrap(), carved);
+        }
+    }
+
+    #[test]
+    fn corrupt_binary_indices_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("corrupt.bin");
+        let path = path.to_str().unwrap();
+        let load = |width: u32, height: u32, steps: &[u32]| {
+            let mut bytes = SeamIndex::MAGIC.to_vec();
+            bytes.extend(width.to_le_bytes());
+            bytes.extend(height.to_le_bytes());
+            bytes.extend(steps.iter().flat_map(|step| step.to_le_bytes()));
+            std::fs::write(path, bytes).unwrap();
+            SeamIndex::load(path)
+        };
+
+        assert_eq!(load(2, 1, &[1, 0]).unwrap().steps().pixels(), &[1, 0]);
+
+        // Negative, huge, truncated and empty sizes.
+        for (width, height) in [
+            (u32::MAX, 1),
+            (1, 0x8000_0000),
+            (0x10000, 0x10000),
+            (2, 2),
+            (0, 0),
+        ] {
+            assert!(
+                matches!(
+                    load(width, height, &[1, 0]),
+                    Err(CarveError::InvalidInput(_))
+                ),
+                "{}x{}",
+                width,
+                height
+            );
+        }
+        assert!(matches!(
+            load(2, 1, &[0, 0]),
+            Err(CarveError::Decode { .. })
+        ));
+    }
+}
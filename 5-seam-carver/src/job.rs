@@ -0,0 +1,482 @@
+use crate::batch::{check_template, find_inputs, format_output};
+use crate::carver::{load_mask_file, EnergyMode, FinderOptions, SeamFinder, SeamOrder, SeamShape};
+use crate::depth::DepthImage;
+use crate::emit::{AnimationOptions, Emissions, EnergySteps};
+use crate::energy::{ColorSpace, EnergyFilter, Saliency};
+use crate::error::{CarveError, CarveResult, PathContext};
+use crate::resize::{Carver, ResizeSummary, TargetSize};
+use crate::util::KernelRect;
+use crate::video::{is_video_path, load_video, save_video, DEFAULT_COHERENCE};
+use cgmath::Vector2;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A carving run described by a TOML or JSON file. Every input is resized to every size. Apart from
+/// `inputs` and `sizes`, every key is the long name of a command line argument (e.g.
+/// `energy-mode` for `--energy-mode`) and flags are set with booleans.
+///
+/// Every path is a template (see [format_output]) so that the outputs of different inputs and
+/// sizes don't overwrite each other, and relative paths are relative to the job file.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Job {
+    /// Images, videos, directories or globs. See [find_inputs].
+    pub inputs: Vec<String>,
+    /// The sizes to which every input is resized. A job without sizes must remove an object.
+    pub sizes: Vec<TargetSize>,
+    /// The outputs aren't saved if this is `None`.
+    pub out: Option<String>,
+    pub order: Option<String>,
+    pub energy: Option<String>,
+    pub energy_radius: Option<i32>,
+    pub energy_mode: Option<String>,
+    pub energy_color: Option<String>,
+    pub lightness_weight: Option<f32>,
+    pub saliency: Option<Saliency>,
+    pub energy_filter: Vec<EnergyFilter>,
+    pub seam_connectivity: Option<i32>,
+    pub seam_width: Option<i32>,
+    pub ignore_alpha: bool,
+    pub stop_at_opaque: bool,
+    pub full_recompute: bool,
+    pub protect_mask: Option<String>,
+    pub remove_mask: Option<String>,
+    pub coherence: Option<f32>,
+    pub threads: Option<usize>,
+    pub timings: bool,
+    pub emit_sobel: Option<EnergySteps>,
+    pub emit_seams: Option<String>,
+    pub emit_seams_on_original: Option<String>,
+    pub emit_animation: Option<String>,
+    pub animation_every: Option<usize>,
+    pub animation_energy: bool,
+}
+
+/// Implements [Deserialize] for types which are parsed from strings on the command line.
+macro_rules! deserialize_from_str {
+    ($($ty:ty),*) => {
+        $(
+            impl<'de> Deserialize<'de> for $ty {
+                fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                    String::deserialize(deserializer)?
+                        .parse()
+                        .map_err(D::Error::custom)
+                }
+            }
+        )*
+    };
+}
+
+deserialize_from_str!(TargetSize, Saliency, EnergyFilter, EnergySteps);
+
+/// An output of a job, or the output a dry run would produce.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JobOutput {
+    pub input: PathBuf,
+    pub output: Option<PathBuf>,
+    pub summary: ResizeSummary,
+    /// How far the output is from its requested size. See [CarveOutput::unreached].
+    ///
+    /// [CarveOutput::unreached]: crate::CarveOutput::unreached
+    pub unreached: Vector2<i32>,
+}
+
+impl Job {
+    /// Loads a job file. Files ending with `.json` are parsed as JSON and every other file is parsed
+    /// as TOML.
+    pub fn load(path: &str) -> CarveResult<Self> {
+        let text = fs::read_to_string(path).reading(path)?;
+        let is_json = Path::new(path)
+            .extension()
+            .map_or(false, |ext| ext.eq_ignore_ascii_case("json"));
+
+        let job = if is_json {
+            serde_json::from_str::<Self>(&text).map_err(|err| err.to_string())
+        } else {
+            toml::from_str::<Self>(&text).map_err(|err| err.to_string())
+        };
+
+        let mut job = job.map_err(|err| CarveError::Decode {
+            path: path.into(),
+            source: err.into(),
+        })?;
+
+        if let Some(dir) = Path::new(path).parent() {
+            job.resolve_paths(dir);
+        }
+        Ok(job)
+    }
+
+    /// Makes the relative paths of the job relative to `dir`. Templates starting with `{dir}` are
+    /// relative to their input and are left as is.
+    pub fn resolve_paths(&mut self, dir: &Path) {
+        let resolve = |path: &mut String| {
+            if Path::new(path.as_str()).is_relative() && !path.starts_with("{dir}") {
+                *path = dir.join(&*path).to_string_lossy().into_owned();
+            }
+        };
+
+        self.inputs.iter_mut().for_each(resolve);
+        [
+            &mut self.out,
+            &mut self.protect_mask,
+            &mut self.remove_mask,
+            &mut self.emit_seams,
+            &mut self.emit_seams_on_original,
+            &mut self.emit_animation,
+        ]
+        .into_iter()
+        .flatten()
+        .for_each(resolve);
+
+        if let Some(energy) = &mut self.emit_sobel {
+            let mut path = energy.path.to_string_lossy().into_owned();
+            resolve(&mut path);
+            energy.path = PathBuf::from(path);
+        }
+    }
+
+    /// Builds the seam finder of the job.
+    pub fn finder(&self) -> CarveResult<SeamFinder> {
+        let unknown = |what: &str, name: &str| {
+            CarveError::InvalidInput(format!("Unknown {} `{}`.", what, name))
+        };
+
+        let defaults = FinderOptions::default();
+        let mode = match &self.energy_mode {
+            Some(name) => {
+                EnergyMode::from_name(name).ok_or_else(|| unknown("energy mode", name))?
+            }
+            None => defaults.mode,
+        };
+        let color = match &self.energy_color {
+            Some(name) => ColorSpace::from_name(name, self.lightness_weight.unwrap_or(1.))
+                .ok_or_else(|| unknown("color space", name))?,
+            None => defaults.color,
+        };
+
+        FinderOptions {
+            mode,
+            energy: self.energy.clone().unwrap_or(defaults.energy),
+            radius: self.energy_radius,
+            color,
+            saliency: self.saliency.clone(),
+            filters: self.energy_filter.clone(),
+            shape: SeamShape {
+                connectivity: self
+                    .seam_connectivity
+                    .unwrap_or(defaults.shape.connectivity),
+                width: self.seam_width.unwrap_or(defaults.shape.width),
+            },
+            incremental: !self.full_recompute,
+        }
+        .build()
+    }
+
+    /// Resizes every input to every size.
+    pub fn run(&self) -> CarveResult<Vec<JobOutput>> {
+        self.run_with(false)
+    }
+
+    /// Describes the outputs of the job without resizing anything.
+    pub fn plan(&self) -> CarveResult<Vec<JobOutput>> {
+        self.run_with(true)
+    }
+
+    fn run_with(&self, dry_run: bool) -> CarveResult<Vec<JobOutput>> {
+        let invalid = |message: String| Err(CarveError::InvalidInput(message));
+        if self.animation_every == Some(0) {
+            return invalid("`animation-every` must be at least 1.".to_string());
+        }
+        if let Some(coherence) = self
+            .coherence
+            .filter(|coherence| coherence.is_nan() || *coherence < 0.)
+        {
+            return invalid(format!(
+                "`coherence` must be non-negative, not {}.",
+                coherence
+            ));
+        }
+        if self.threads == Some(0) {
+            return invalid("`threads` must be at least 1.".to_string());
+        }
+
+        let finder = self.finder()?;
+        let order = match &self.order {
+            Some(name) => SeamOrder::from_name(name).ok_or_else(|| {
+                CarveError::InvalidInput(format!("Unknown seam order `{}`.", name))
+            })?,
+            None => SeamOrder::WidthFirst,
+        };
+
+        let mut inputs = Vec::new();
+        for pattern in &self.inputs {
+            inputs.extend(find_inputs(pattern)?);
+        }
+        if inputs.is_empty() {
+            return Err(CarveError::InvalidInput(
+                "The job has no inputs.".to_string(),
+            ));
+        }
+        if self.sizes.is_empty() && self.remove_mask.is_none() {
+            return Err(CarveError::InvalidInput(
+                "The job must either resize its inputs or remove an object from them.".to_string(),
+            ));
+        }
+
+        // Outputs must not overwrite each other.
+        let emit_sobel = self
+            .emit_sobel
+            .as_ref()
+            .map(|energy| energy.path.to_string_lossy().into_owned());
+        for template in [
+            &self.out,
+            &emit_sobel,
+            &self.emit_seams,
+            &self.emit_seams_on_original,
+            &self.emit_animation,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            check_template(template, &inputs, &self.sizes)?;
+        }
+
+        let sizes = match self.sizes.len() {
+            0 => vec![None],
+            _ => self.sizes.iter().copied().map(Some).collect(),
+        };
+
+        let mut outputs = Vec::new();
+        for input in &inputs {
+            let input_str = input.to_string_lossy();
+            let video = is_video_path(&input_str)
+                .then(|| load_video(&input_str))
+                .transpose()?;
+            let image = match &video {
+                Some(video) => DepthImage::Rgba8(video.frames[0].clone()),
+                None => DepthImage::open(input)?,
+            };
+            let from_size = image.size();
+
+            for size in &sizes {
+                let to_size = size.map(|size| size.resolve(from_size));
+                let path =
+                    |template: &str| format_output(template, input, to_size.unwrap_or(from_size));
+
+                let emissions = Emissions {
+                    energy: self.emit_sobel.as_ref().map(|energy| {
+                        EnergySteps::new(
+                            path(&energy.path.to_string_lossy()),
+                            energy.steps().to_vec(),
+                        )
+                    }),
+                    seams_on_energy: self.emit_seams.as_deref().map(path),
+                    seams_on_original: self.emit_seams_on_original.as_deref().map(path),
+                    animation: self
+                        .emit_animation
+                        .as_deref()
+                        .map(|template| AnimationOptions {
+                            path: path(template),
+                            every: self.animation_every.unwrap_or(1),
+                            energy_panel: self.animation_energy,
+                        }),
+                };
+                let output_path = self.out.as_deref().map(path);
+
+                // Missing directories are created, like those of batch outputs.
+                if !dry_run {
+                    let emitted = [
+                        emissions.energy.as_ref().map(|energy| &energy.path),
+                        emissions.seams_on_energy.as_ref(),
+                        emissions.seams_on_original.as_ref(),
+                        emissions
+                            .animation
+                            .as_ref()
+                            .map(|animation| &animation.path),
+                    ];
+                    for path in emitted.into_iter().chain([output_path.as_ref()]).flatten() {
+                        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+                            fs::create_dir_all(dir).writing(dir)?;
+                        }
+                    }
+                }
+
+                let mut carver = Carver::new()
+                    .finder(finder.clone())
+                    .order(order)
+                    .emit(emissions);
+                if let Some(size) = to_size {
+                    carver = carver.size(size);
+                }
+                if let Some(template) = &self.protect_mask {
+                    carver = carver.protect_mask(load_mask_file(path(template))?);
+                }
+                if let Some(template) = &self.remove_mask {
+                    carver = carver.remove_mask(load_mask_file(path(template))?);
+                }
+                if self.ignore_alpha {
+                    carver = carver.ignore_alpha();
+                }
+                if self.stop_at_opaque {
+                    carver = carver.stop_at_opaque();
+                }
+
+                let frames = video.as_ref().map(|video| video.frames.len());
+                let mut output = JobOutput {
+                    input: input.clone(),
+                    output: output_path,
+                    summary: carver.summary(from_size, frames),
+                    unreached: Vector2::new(0, 0),
+                };
+
+                if !dry_run {
+                    // Videos are carved frame by frame using the plan of their first frame.
+                    if let Some(video) = &video {
+                        let mut video = video.clone();
+                        carver
+                            .run_video(&mut video, self.coherence.unwrap_or(DEFAULT_COHERENCE))?;
+                        if let Some(path) = &output.output {
+                            save_video(&path.to_string_lossy(), &video)?;
+                        }
+                    } else {
+                        let carved = carver.run_depth(image.clone())?;
+                        if let Some(path) = &output.output {
+                            carved.image.save(path)?;
+                        }
+                        output.unreached = carved.unreached;
+                    }
+                }
+                outputs.push(output);
+            }
+        }
+
+        Ok(outputs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn toml_and_json_jobs_agree() {
+        let toml = toml::from_str::<Job>(
+            r#"
+            # Resize the cat
+            inputs = ["images/cat.png"]
+            sizes = ['600x650', "?-10xP"] # Trailing comment
+            energy-radius = 4
+            energy-filter = ["median:1", "blur:2"]
+            coherence = 0.5
+            full-recompute = true
+            "#,
+        )
+        .unwrap();
+
+        let json = serde_json::from_str::<Job>(
+            r#"{
+                "inputs": ["images/cat.png"],
+                "sizes": ["600x650", "?-10xP"],
+                "energy-radius": 4,
+                "energy-filter": ["median:1", "blur:2"],
+                "coherence": 0.5,
+                "full-recompute": true
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(toml, json);
+        assert_eq!(toml.energy_radius, Some(4));
+        assert_eq!(toml.sizes[1], "?-10xP".parse().unwrap());
+        assert!(toml::from_str::<Job>("sizes = [\"600x650\"]\nsizes = [\"1x1\"]").is_err());
+        assert!(toml::from_str::<Job>("sizes = [\"600\"]").is_err());
+        assert!(serde_json::from_str::<Job>("{\"size\": [600, 650]}").is_err());
+    }
+
+    #[test]
+    fn jobs_resize_every_input_to_every_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let cat = image::open(format!("{}/images/cat.png", env!("CARGO_MANIFEST_DIR")))
+            .unwrap()
+            .into_rgba8();
+        fs::create_dir(dir.path().join("in")).unwrap();
+        for (name, width) in [("wide", 48), ("tall", 32)] {
+            let image = image::imageops::thumbnail(&cat, width, 40);
+            image
+                .save(dir.path().join("in").join(format!("{}.png", name)))
+                .unwrap();
+        }
+
+        // Paths are relative to the job file.
+        let job_path = dir.path().join("job.toml");
+        fs::write(
+            &job_path,
+            r#"
+            inputs = ["in/*.png"]
+            sizes = ["30x40", "?-2x?-4"]
+            out = "out/{stem}_{w}x{h}.png"
+            emit-seams = "out/{stem}_{w}x{h}.seams.png"
+            "#,
+        )
+        .unwrap();
+
+        let job = Job::load(&job_path.to_string_lossy()).unwrap();
+        let planned = job.plan().unwrap();
+        assert!(
+            !dir.path().join("out").exists(),
+            "dry runs must not save anything"
+        );
+
+        let outputs = job.run().unwrap();
+        assert_eq!(outputs, planned);
+
+        let expected = [
+            ("tall_30x40.png", (30, 40)),
+            ("tall_30x36.png", (30, 36)),
+            ("wide_30x40.png", (30, 40)),
+            ("wide_46x36.png", (46, 36)),
+        ];
+        assert_eq!(outputs.len(), expected.len());
+        for (output, (name, size)) in outputs.iter().zip(expected) {
+            let path = dir.path().join("out").join(name);
+            assert_eq!(output.output.as_ref(), Some(&path));
+
+            let image = image::open(&path).unwrap().into_rgba8();
+            assert_eq!(image.dimensions(), size);
+            assert!(path.with_extension("seams.png").exists());
+        }
+
+        // Outputs which would overwrite each other are rejected before anything is carved.
+        let mut job = job;
+        job.out = Some(dir.path().join("out.png").to_string_lossy().into_owned());
+        assert!(job.run().is_err());
+        assert!(!dir.path().join("out.png").exists());
+    }
+
+    #[test]
+    fn out_of_range_values_are_rejected() {
+        for line in [
+            "animation-every = 0",
+            "seam-connectivity = 500",
+            "seam-width = 0",
+            "energy-radius = -3",
+            "energy-color = \"lab\"\nlightness-weight = -1.0",
+            "coherence = -0.5",
+            "threads = 0",
+        ] {
+            let job = toml::from_str::<Job>(&format!(
+                "inputs = [\"images/cat.png\"]\nsizes = [\"600x650\"]\n{}",
+                line
+            ))
+            .unwrap();
+            assert!(
+                matches!(job.plan(), Err(CarveError::InvalidInput(_))),
+                "`{}` is rejected",
+                line
+            );
+        }
+    }
+}
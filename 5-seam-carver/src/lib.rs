@@ -0,0 +1,18 @@
+//! Content-aware image resizing using seam carving. See [Carver] for the main entry point.
+
+pub mod batch;
+pub mod carved;
+pub mod carver;
+pub mod depth;
+pub mod emit;
+pub mod energy;
+pub mod error;
+pub mod index;
+pub mod job;
+pub mod resize;
+pub mod stream;
+pub mod util;
+pub mod video;
+
+pub use resize::{CarveOutput, Carver};
+pub use util::{Kernel, KernelRect, VecKernel};
@@ -0,0 +1,979 @@
+use crate::carved::CarvedKernel;
+use crate::carver::{
+    find_seams, has_transparency, insert_blended, mask_any, opaque_content_mask, removal_axis,
+    Axis, EnergyMode, LowestDerivative, Masks, SeamCache, SeamFinder, SeamMask, SeamOrder,
+    OPAQUE_ALPHA,
+};
+use crate::depth::{ColorPixel, DepthImage};
+use crate::emit::Emissions;
+use crate::energy::EnergyFunction;
+use crate::error::{CarveError, CarveResult};
+use crate::index::{SeamIndex, SeamTracker};
+use crate::util::{ColorKernel, IterCollectArrayExt, Kernel, KernelRect, Timer, WeightImage};
+use crate::video::{carve_video, Video};
+use cgmath::Vector2;
+use image::{Luma, RgbaImage};
+use std::cmp::Ordering;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// Resizes images by carving out and inserting seams.
+///
+/// ```no_run
+/// # use seam_carver::Carver;
+/// # use cgmath::Vector2;
+/// let image = image::open("images/cat.png").unwrap().into_rgba8();
+/// let output = Carver::new().size(Vector2::new(600, 650)).run(image).unwrap();
+/// output.image.save("cat.out.png").unwrap();
+/// ```
+pub struct Carver<'a> {
+    finder: SeamFinder,
+    order: SeamOrder,
+    size: Option<Vector2<i32>>,
+    protect_mask: Option<WeightImage>,
+    remove_mask: Option<WeightImage>,
+    track_seams: bool,
+    ignore_alpha: bool,
+    stop_at_opaque: bool,
+    emit: Emissions,
+    on_plan: Option<PlanHandler<'a>>,
+    on_pass: Option<PassHandler<'a>>,
+}
+
+type PlanHandler<'a> = Box<dyn FnMut(&Plan) -> CarveResult<()> + 'a>;
+type PassHandler<'a> = Box<dyn FnMut(&Pass) -> CarveResult<()> + 'a>;
+
+impl Default for Carver<'_> {
+    fn default() -> Self {
+        Self {
+            finder: SeamFinder::default(),
+            order: SeamOrder::WidthFirst,
+            size: None,
+            protect_mask: None,
+            remove_mask: None,
+            track_seams: false,
+            ignore_alpha: false,
+            stop_at_opaque: false,
+            emit: Emissions::default(),
+            on_plan: None,
+            on_pass: None,
+        }
+    }
+}
+
+impl<'a> Carver<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The size to which the image is resized. Defaults to the size of the image once the object
+    /// marked by the removal mask has been removed.
+    pub fn size(mut self, size: Vector2<i32>) -> Self {
+        self.size = Some(size);
+        self
+    }
+
+    /// Replaces the energy function, energy mode and update strategy all at once.
+    pub fn finder(mut self, finder: SeamFinder) -> Self {
+        self.finder = finder;
+        self
+    }
+
+    pub fn energy(mut self, energy: Arc<dyn EnergyFunction>) -> Self {
+        self.finder.energy = energy;
+        self
+    }
+
+    pub fn energy_mode(mut self, mode: EnergyMode) -> Self {
+        self.finder.mode = mode;
+        self
+    }
+
+    /// The order in which vertical and horizontal seams are removed when both dimensions shrink.
+    pub fn order(mut self, order: SeamOrder) -> Self {
+        self.order = order;
+        self
+    }
+
+    /// Prevents seams from crossing the pixels of the mask in proportion to their weight.
+    pub fn protect_mask(mut self, mask: WeightImage) -> Self {
+        self.protect_mask = Some(mask);
+        self
+    }
+
+    /// Removes the object marked by the non-zero pixels of the mask before resizing the image.
+    pub fn remove_mask(mut self, mask: WeightImage) -> Self {
+        self.remove_mask = Some(mask);
+        self
+    }
+
+    /// Records when every pixel of the original image was carved out or inserted into
+    /// [CarveOutput::seams].
+    pub fn track_seams(mut self) -> Self {
+        self.track_seams = true;
+        self
+    }
+
+    /// Keeps [SeamFinder::alpha] as it is rather than enabling it for images with transparency.
+    pub fn ignore_alpha(mut self) -> Self {
+        self.ignore_alpha = true;
+        self
+    }
+
+    /// Stops shrinking an axis once every seam along it would have to cross opaque content (see
+    /// [opaque_content_mask]) rather than distorting the content. How many columns and rows were
+    /// left is reported in [CarveOutput::unreached].
+    pub fn stop_at_opaque(mut self) -> Self {
+        self.stop_at_opaque = true;
+        self
+    }
+
+    /// Saves the requested debug views of the resize. Videos don't support any of them.
+    pub fn emit(mut self, emissions: Emissions) -> Self {
+        self.emit = emissions;
+        self
+    }
+
+    /// Called once the axes of the seams to carve have been determined. Returning an error aborts
+    /// the resize.
+    pub fn on_plan<F>(mut self, handler: F) -> Self
+    where
+        F: FnMut(&Plan) -> CarveResult<()> + 'a,
+    {
+        self.on_plan = Some(Box::new(handler));
+        self
+    }
+
+    /// Called before every seam is carved out and before every batch of seams is inserted.
+    /// Returning an error aborts the resize.
+    pub fn on_pass<F>(mut self, handler: F) -> Self
+    where
+        F: FnMut(&Pass) -> CarveResult<()> + 'a,
+    {
+        self.on_pass = Some(Box::new(handler));
+        self
+    }
+
+    /// Resizes `image`, which can hold pixels of any [ColorPixel] type.
+    pub fn run<K>(mut self, image: K) -> CarveResult<CarveOutput<K>>
+    where
+        K: Kernel + Sync,
+        K::Pixel: ColorPixel,
+    {
+        let from_size = image.size();
+        let mut masks = self.masks(from_size)?;
+        self.detect_alpha(&image);
+
+        // The animation's canvas must fit the image at every step of the resize.
+        let canvas = self.size.map_or(from_size, |size| {
+            Vector2::new(from_size.x.max(size.x), from_size.y.max(size.y))
+        });
+        let mut recorder = self.emit.start(canvas)?;
+        let original = self.emit.paints_seams().then(|| image.clone());
+
+        // Opaque content is protected so that the cheapest seam only crosses it once every seam
+        // does.
+        if self.stop_at_opaque {
+            let mut content = opaque_content_mask(&image);
+            if let Some(protect) = masks.protect.take() {
+                for (Luma([content]), Luma([protect])) in
+                    content.pixels_mut().zip(protect.into_inner().pixels())
+                {
+                    *content = content.max(*protect);
+                }
+            }
+            masks.protect = Some(CarvedKernel::from(content));
+        }
+
+        let mut image = CarvedKernel::from(image);
+        let mut tracker =
+            (self.track_seams || self.emit.paints_seams()).then(|| SeamTracker::new(from_size));
+
+        // The index of the next seam to be carved or inserted.
+        let mut i = 0;
+
+        // Object removal pass
+        let removal_axis = masks.remove.as_ref().and_then(removal_axis);
+        if let Some(axis) = removal_axis {
+            let _outer = Timer::start("remove_object");
+            let mut cache = SeamCache::new(&self.finder, &image, axis, &masks);
+
+            // Any seam crossing the mask should be cheaper than every seam which doesn't, but a
+            // protection mask can outweigh it. We stop as soon as the cheapest seam misses the mask
+            // rather than carving away the rest of the image.
+            while masks.remove.as_ref().map_or(false, mask_any) && axis.across(image.size()) > 1 {
+                let _inner = Timer::start("remove_pass");
+                let seam = cache.seam();
+                let remove = masks.remove.as_ref().unwrap();
+                if !seam.positions().any(|pos| remove.get(pos).0[0] > 0.) {
+                    break;
+                }
+
+                if let Some(tracker) = &mut tracker {
+                    let _timer = Timer::start("update_seams");
+                    tracker.carve(seam, i);
+                }
+
+                let pass = Pass::Carve(CarvePass {
+                    phase: Phase::Removal,
+                    seam_index: i,
+                    image: &image,
+                    finder: &self.finder,
+                    cache: &cache,
+                    masks: &masks,
+                });
+                self.emit.pass(&pass, recorder.as_mut())?;
+                if let Some(on_pass) = &mut self.on_pass {
+                    on_pass(&pass)?;
+                }
+
+                // Carve out the seam from the main image and the masks
+                image.carve(axis, seam.iter());
+                masks.carve(seam);
+                cache.carve(&self.finder, &image, &masks);
+                i += 1;
+            }
+        }
+
+        // The removal mask has done its job and must not affect the resize.
+        masks.remove = None;
+
+        let (steps, grow_by) = self.plan(&image, &masks, from_size)?;
+        let first_axis = removal_axis
+            .or_else(|| steps.first().copied())
+            .unwrap_or(Axis::Vertical);
+
+        // The number of columns and rows which couldn't be carved out without crossing opaque
+        // content.
+        let mut unreached = Vector2::new(0, 0);
+
+        // Main pass
+        {
+            let _outer = Timer::start("main");
+
+            // The energy and seam weights of the image are kept up to date across passes along the
+            // same axis and only rebuilt when the axis changes.
+            let mut cache: Option<SeamCache> = None;
+
+            for (step_i, axis) in steps.iter().copied().enumerate() {
+                let _inner = Timer::start("resize_pass");
+
+                // Once an axis is blocked by opaque content, none of its remaining seams are carved.
+                if axis.across(unreached) > 0 {
+                    unreached += axis.unit();
+                    continue;
+                }
+
+                if !matches!(&cache, Some(cache) if cache.axis() == axis) {
+                    cache = Some(SeamCache::new(&self.finder, &image, axis, &masks));
+                }
+                let cache = cache.as_mut().unwrap();
+
+                // Fetch the lowest weighted seam in the image
+                let seam = cache.seam();
+
+                if self.stop_at_opaque
+                    && seam
+                        .positions()
+                        .any(|pos| image.get(pos).to_vec4().w >= OPAQUE_ALPHA)
+                {
+                    unreached += axis.unit();
+                    continue;
+                }
+
+                if let Some(tracker) = &mut tracker {
+                    let _timer = Timer::start("update_seams");
+                    tracker.carve(seam, i);
+                }
+
+                let pass = Pass::Carve(CarvePass {
+                    phase: Phase::Resize {
+                        step: step_i as i32,
+                    },
+                    seam_index: i,
+                    image: &image,
+                    finder: &self.finder,
+                    cache,
+                    masks: &masks,
+                });
+                self.emit.pass(&pass, recorder.as_mut())?;
+                if let Some(on_pass) = &mut self.on_pass {
+                    on_pass(&pass)?;
+                }
+
+                // Carve out the seam from the main image and the masks
+                image.carve(axis, seam.iter());
+                masks.carve(seam);
+                cache.carve(&self.finder, &image, &masks);
+                i += 1;
+            }
+
+            // Insert seams along the axes which grow. Inserting too many seams at once would cause
+            // the same low-weight seams to be stretched over and over so we split large
+            // enlargements into stages which grow the image by at most half its current size.
+            for (axis, mut remaining) in
+                [(Axis::Vertical, grow_by.x), (Axis::Horizontal, grow_by.y)]
+            {
+                while remaining > 0 {
+                    let _inner = Timer::start("insert_pass");
+                    let count = remaining.min((axis.across(image.size()) / 2).max(1));
+                    let seams = find_seams(&image, &self.finder, axis, count, &masks);
+
+                    if let Some(tracker) = &mut tracker {
+                        let _timer = Timer::start("update_seams");
+                        tracker.insert(&seams, axis, count, i);
+                    }
+
+                    let pass = Pass::Insert(InsertPass {
+                        seam_index: i,
+                        image: &image,
+                        finder: &self.finder,
+                        axis,
+                        seams: &seams,
+                        count,
+                        masks: &masks,
+                    });
+                    self.emit.pass(&pass, recorder.as_mut())?;
+                    if let Some(on_pass) = &mut self.on_pass {
+                        on_pass(&pass)?;
+                    }
+
+                    // Duplicate the seams in the main image and the masks
+                    image = insert_blended(&image, axis, &seams, count);
+                    masks.insert(axis, &seams, count);
+                    remaining -= count;
+                    i += count;
+                }
+            }
+        }
+
+        // Save the debug views
+        if let Some(recorder) = recorder {
+            let energy = || {
+                let mut energy = self.finder.energy.energy(&image, first_axis);
+                masks.apply(&mut energy);
+                energy
+            };
+            recorder.finish(&image, energy)?;
+        }
+
+        if let (Some(original), Some(tracker)) = (&original, &tracker) {
+            let energy = || self.finder.energy.energy(original, first_axis);
+            self.emit.paint_seams(original, energy, tracker, i)?;
+        }
+
+        Ok(CarveOutput {
+            image: image.into_inner(),
+            seams: tracker.filter(|_| self.track_seams),
+            seam_count: i,
+            first_axis,
+            masks,
+            unreached,
+        })
+    }
+
+    /// Resizes `image` at its own depth.
+    pub fn run_depth(self, image: DepthImage) -> CarveResult<CarveOutput<DepthImage>> {
+        Ok(match image {
+            DepthImage::Rgba8(image) => self.run(image)?.map_image(DepthImage::Rgba8),
+            DepthImage::Rgba16(image) => self.run(image)?.map_image(DepthImage::Rgba16),
+            DepthImage::Rgba32F(image) => self.run(image)?.map_image(DepthImage::Rgba32F),
+        })
+    }
+
+    /// Carves every frame of `video` with the seam axes planned for its first frame. See
+    /// [carve_video]. Videos can only be shrunk and don't support removal masks, stopping at opaque
+    /// content or debug views.
+    pub fn run_video(mut self, video: &mut Video, coherence: f32) -> CarveResult<()> {
+        if self.remove_mask.is_some() || self.stop_at_opaque || !self.emit.is_empty() {
+            return Err(CarveError::InvalidInput(
+                "Removal masks, stopping at opaque content and debug views are not supported for \
+                 videos."
+                    .to_string(),
+            ));
+        }
+
+        // A single frame with transparency switches the whole video over so that every frame is
+        // carved alike.
+        for frame in &video.frames {
+            self.detect_alpha(frame);
+        }
+
+        let image = CarvedKernel::from(video.frames[0].clone());
+        let from_size = image.size();
+        let masks = self.masks(from_size)?;
+        let (steps, grow_by) = self.plan(&image, &masks, from_size)?;
+        if grow_by != Vector2::new(0, 0) {
+            let to_size = self.size.unwrap_or(from_size);
+            return Err(CarveError::InvalidSize(format!(
+                "Videos can only be shrunk. (wants resize from {}x{} to {}x{})",
+                from_size.x, from_size.y, to_size.x, to_size.y
+            )));
+        }
+
+        carve_video(video, &self.finder, &steps, &masks, coherence);
+        Ok(())
+    }
+
+    /// Builds a [SeamIndex] of `image` with the carver's finder and protection mask. The other
+    /// settings of the carver don't apply to indices.
+    pub fn index<K>(mut self, image: &K) -> CarveResult<SeamIndex>
+    where
+        K: Kernel + Sync,
+        K::Pixel: ColorPixel,
+    {
+        let masks = self.masks(image.size())?;
+        self.detect_alpha(image);
+        Ok(SeamIndex::build(image, &self.finder, &masks))
+    }
+
+    /// Builds a [SeamIndex] of `image` at its own depth.
+    pub fn index_depth(self, image: &DepthImage) -> CarveResult<SeamIndex> {
+        match image {
+            DepthImage::Rgba8(image) => self.index(image),
+            DepthImage::Rgba16(image) => self.index(image),
+            DepthImage::Rgba32F(image) => self.index(image),
+        }
+    }
+
+    /// Describes what resizing an image of size `from_size` would do, without resizing anything.
+    /// `frames` is the number of frames of a video.
+    pub fn summary(&self, from_size: Vector2<i32>, frames: Option<usize>) -> ResizeSummary {
+        ResizeSummary {
+            from_size,
+            to_size: self.size,
+            removes_object: self.remove_mask.is_some(),
+            frames,
+        }
+    }
+
+    /// Enables [SeamFinder::alpha] for images with transparency unless [Carver::ignore_alpha] was
+    /// requested.
+    fn detect_alpha(&mut self, image: &dyn ColorKernel) {
+        if !self.ignore_alpha && has_transparency(image) {
+            self.finder.alpha = true;
+        }
+    }
+
+    /// Builds the masks of an image of the given size.
+    fn masks(&mut self, size: Vector2<i32>) -> CarveResult<Masks> {
+        for (mask, name) in [
+            (&self.protect_mask, "protection"),
+            (&self.remove_mask, "removal"),
+        ] {
+            if let Some(mask) = mask.as_ref().filter(|mask| mask.size() != size) {
+                return Err(CarveError::InvalidInput(format!(
+                    "The {} mask must have the same dimensions as the input image. \
+                     (mask is {}x{} but image is {}x{})",
+                    name,
+                    mask.width(),
+                    mask.height(),
+                    size.x,
+                    size.y
+                )));
+            }
+        }
+
+        Ok(Masks {
+            protect: self.protect_mask.take().map(CarvedKernel::from),
+            remove: self.remove_mask.take().map(CarvedKernel::from),
+            guide: None,
+        })
+    }
+
+    /// Determines the axis of every seam to carve and how much every axis must grow by, once the
+    /// object marked by the removal mask has been removed from `image`. Seams are weighed with
+    /// `masks` applied.
+    fn plan<K>(
+        &mut self,
+        image: &CarvedKernel<K>,
+        masks: &Masks,
+        from_size: Vector2<i32>,
+    ) -> CarveResult<(Vec<Axis>, Vector2<i32>)>
+    where
+        K: Kernel + Sync,
+        K::Pixel: ColorPixel,
+    {
+        // Validate size parameters
+        let curr_size = image.size();
+        let to_size = self.size.unwrap_or(curr_size);
+
+        if to_size.x <= 0 {
+            return Err(CarveError::InvalidSize(format!(
+                "Target width must be greater than 0. (wants resize from {} to {})",
+                from_size.x, to_size.x
+            )));
+        }
+
+        if to_size.y <= 0 {
+            return Err(CarveError::InvalidSize(format!(
+                "Target height must be greater than 0. (wants resize from {} to {})",
+                from_size.y, to_size.y
+            )));
+        }
+
+        // Determine the axis of every seam we're going to carve. Growing axes are handled
+        // separately by seam insertion once all the carving is done.
+        let shrink_by = Vector2::new(
+            (curr_size.x - to_size.x).max(0),
+            (curr_size.y - to_size.y).max(0),
+        );
+        let grow_by = Vector2::new(
+            (to_size.x - curr_size.x).max(0),
+            (to_size.y - curr_size.y).max(0),
+        );
+        let steps = self
+            .order
+            .plan(&image.compacted(), &self.finder, masks, shrink_by);
+
+        let plan = Plan {
+            image,
+            finder: &self.finder,
+            steps: &steps,
+            grow_by,
+        };
+        self.emit.plan(&plan)?;
+        if let Some(on_plan) = &mut self.on_plan {
+            on_plan(&plan)?;
+        }
+
+        Ok((steps, grow_by))
+    }
+}
+
+/// The seams which will be carved out of and inserted into an image.
+pub struct Plan<'a> {
+    /// The image once the object marked by the removal mask has been removed.
+    pub image: &'a dyn ColorKernel,
+    pub finder: &'a SeamFinder,
+    /// The axis of every seam which will be carved out of the image, in order.
+    pub steps: &'a [Axis],
+    /// The number of seams which will be inserted along each axis once carving is done.
+    pub grow_by: Vector2<i32>,
+}
+
+/// A single step of a resize, reported to [Carver::on_pass] before it is applied.
+pub enum Pass<'a> {
+    Carve(CarvePass<'a>),
+    Insert(InsertPass<'a>),
+}
+
+impl Pass<'_> {
+    /// The image before the pass is applied.
+    pub fn image(&self) -> &dyn ColorKernel {
+        match self {
+            Pass::Carve(pass) => pass.image,
+            Pass::Insert(pass) => pass.image,
+        }
+    }
+
+    /// The index of the (first) seam carved out or inserted by this pass.
+    pub fn seam_index(&self) -> i32 {
+        match self {
+            Pass::Carve(pass) => pass.seam_index,
+            Pass::Insert(pass) => pass.seam_index,
+        }
+    }
+
+    /// The energy which was used to find the seams of this pass, for debugging purposes.
+    pub fn energy(&self) -> WeightImage {
+        match self {
+            Pass::Carve(pass) => pass.energy(),
+            Pass::Insert(pass) => pass.energy(),
+        }
+    }
+
+    /// The positions of every pixel carved out or inserted by this pass.
+    pub fn positions(&self) -> Vec<Vector2<i32>> {
+        match self {
+            Pass::Carve(pass) => pass.seam().positions().collect(),
+            Pass::Insert(pass) => (0..pass.seams.dim())
+                .filter(|index| pass.seams.pixels()[*index].is_some())
+                .map(|index| pass.seams.decode_pos(index))
+                .collect(),
+        }
+    }
+}
+
+/// Which part of the resize a seam is carved out by.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Phase {
+    /// The object marked by the removal mask is being removed.
+    Removal,
+    /// The image is being shrunk. `step` indexes into [Plan::steps].
+    Resize { step: i32 },
+}
+
+/// A seam which is about to be carved out of the image.
+pub struct CarvePass<'a> {
+    pub phase: Phase,
+    pub seam_index: i32,
+    pub image: &'a dyn ColorKernel,
+    pub finder: &'a SeamFinder,
+    pub cache: &'a SeamCache,
+    pub masks: &'a Masks,
+}
+
+impl CarvePass<'_> {
+    pub fn seam(&self) -> &LowestDerivative {
+        self.cache.seam()
+    }
+
+    /// The weight of every pixel, including the weight of the masks. Forward energy doesn't give
+    /// pixels any weight of their own so the cumulative seam weights are used instead.
+    pub fn energy(&self) -> WeightImage {
+        match self.finder.mode {
+            EnergyMode::Backward => {
+                let mut energy = self.cache.energy().compacted();
+                self.masks.apply(&mut energy);
+                energy
+            }
+            EnergyMode::Forward => self.cache.seam().weights().compacted(),
+        }
+    }
+}
+
+/// A batch of seams which is about to be inserted into the image.
+pub struct InsertPass<'a> {
+    pub seam_index: i32,
+    pub image: &'a dyn ColorKernel,
+    pub finder: &'a SeamFinder,
+    pub axis: Axis,
+    pub seams: &'a SeamMask,
+    pub count: i32,
+    pub masks: &'a Masks,
+}
+
+impl InsertPass<'_> {
+    /// The energy of every pixel, including the weight of the masks.
+    pub fn energy(&self) -> WeightImage {
+        let mut energy = self.finder.energy.energy(self.image, self.axis);
+        self.masks.apply(&mut energy);
+        energy
+    }
+}
+
+/// The result of [Carver::run].
+pub struct CarveOutput<K = RgbaImage> {
+    pub image: K,
+    /// When every pixel of the original image was carved out or inserted, if
+    /// [Carver::track_seams] was requested.
+    pub seams: Option<SeamTracker>,
+    /// The number of seams which were carved out or inserted.
+    pub seam_count: i32,
+    /// The axis of the first seam, or [Axis::Vertical] if no seam was carved.
+    pub first_axis: Axis,
+    /// The masks, carved and inserted into alongside the image.
+    pub masks: Masks,
+    /// The number of columns and rows which were left in the image because every seam would have
+    /// crossed opaque content. See [Carver::stop_at_opaque].
+    pub unreached: Vector2<i32>,
+}
+
+impl<K> CarveOutput<K> {
+    /// Replaces the image of the output, keeping everything else.
+    pub fn map_image<L>(self, map: impl FnOnce(K) -> L) -> CarveOutput<L> {
+        CarveOutput {
+            image: map(self.image),
+            seams: self.seams,
+            seam_count: self.seam_count,
+            first_axis: self.first_axis,
+            masks: self.masks,
+            unreached: self.unreached,
+        }
+    }
+}
+
+/// What a resize will do, as reported by dry runs. See [Carver::summary].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ResizeSummary {
+    pub from_size: Vector2<i32>,
+    /// The size to which the image is resized, if any.
+    pub to_size: Option<Vector2<i32>>,
+    /// Whether an object is removed from the image before it is resized.
+    pub removes_object: bool,
+    /// The number of frames of a video, or `None` for still images.
+    pub frames: Option<usize>,
+}
+
+impl Display for ResizeSummary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        let kind = match self.frames {
+            Some(frames) => format!("video of {} frames", frames),
+            None => "image".to_string(),
+        };
+        let axis = |name: &str, from: i32, to: i32| match to.cmp(&from) {
+            Ordering::Less => format!("removing {} {}s", from - to, name),
+            Ordering::Greater => format!("inserting {} {}s", to - from, name),
+            Ordering::Equal => format!("keeping every {}", name),
+        };
+        let from_size = self.from_size;
+
+        match (self.to_size, self.removes_object) {
+            // The size of the image after the object is removed is only known once it is removed.
+            (to_size, true) => {
+                write!(
+                    f,
+                    "remove the object marked by the removal mask from the {} of {}x{}",
+                    kind, from_size.x, from_size.y
+                )?;
+                if let Some(to_size) = to_size {
+                    write!(f, " and resize it to {}x{}", to_size.x, to_size.y)?;
+                }
+                write!(f, ".")
+            }
+            (Some(to_size), false) => write!(
+                f,
+                "resize the {} of {}x{} to {}x{} by {} and {}.",
+                kind,
+                from_size.x,
+                from_size.y,
+                to_size.x,
+                to_size.y,
+                axis("column", from_size.x, to_size.x),
+                axis("row", from_size.y, to_size.y)
+            ),
+            (None, false) => write!(
+                f,
+                "keep the {} of {}x{} as it is.",
+                kind, from_size.x, from_size.y
+            ),
+        }
+    }
+}
+
+/// A size given as `WIDTHxHEIGHT`. Components are absolute by default but can be made relative to
+/// the size of the input image with a leading `?` (e.g. `?20x?-30`) and preserving with `P`
+/// (e.g. `300xP`).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TargetSize {
+    pub width: SizeComp,
+    pub height: SizeComp,
+}
+
+/// A single component of a [TargetSize].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SizeComp {
+    pub is_rel: bool,
+    pub val: i32,
+}
+
+impl TargetSize {
+    /// The size to which an image of size `from_size` should be resized.
+    pub fn resolve(&self, from_size: Vector2<i32>) -> Vector2<i32> {
+        Vector2::new(
+            self.width.resolve(from_size.x),
+            self.height.resolve(from_size.y),
+        )
+    }
+}
+
+impl SizeComp {
+    fn resolve(&self, from: i32) -> i32 {
+        if self.is_rel {
+            from + self.val
+        } else {
+            self.val
+        }
+    }
+}
+
+impl FromStr for TargetSize {
+    type Err = String;
+
+    fn from_str(arg: &str) -> Result<Self, Self::Err> {
+        const FORM_ERR: &str =
+            "Argument must take the form `WIDTHxHEIGHT`. See help for more details.";
+
+        // Split up components
+        let [left, right] = arg
+            .split('x')
+            .try_collect_array()
+            .map_err(|_| FORM_ERR.to_string())?;
+
+        // Validate components
+        fn parse_comp(mut comp: &str) -> Result<SizeComp, String> {
+            // Parse prefix
+            let is_rel = match comp.chars().next() {
+                Some('p' | 'P') => {
+                    return if comp.len() == 1 {
+                        Ok(SizeComp {
+                            is_rel: true,
+                            val: 0,
+                        })
+                    } else {
+                        Err(FORM_ERR.to_string())
+                    }
+                }
+                Some('?') => {
+                    comp = &comp[1..];
+                    true
+                }
+                None => return Err(FORM_ERR.to_string()),
+                _ => false,
+            };
+
+            // Parse digits
+            let val = comp.parse::<i32>().map_err(|_| FORM_ERR.to_string())?;
+
+            Ok(SizeComp { is_rel, val })
+        }
+
+        Ok(Self {
+            width: parse_comp(left)?,
+            height: parse_comp(right)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn open_thumbnail(width: u32, height: u32) -> RgbaImage {
+        let path = format!("{}/images/cat.png", env!("CARGO_MANIFEST_DIR"));
+        let image = image::open(path).unwrap().into_rgba8();
+        image::imageops::resize(&image, width, height, image::imageops::Triangle)
+    }
+
+    #[test]
+    fn large_enlargements_are_split_into_stages() {
+        let image = open_thumbnail(30, 20);
+        let mut stages = Vec::new();
+        let output = Carver::new()
+            .size(Vector2::new(75, 20))
+            .on_pass(|pass| {
+                if let Pass::Insert(pass) = pass {
+                    let size = pass.image.size();
+                    assert_eq!(pass.axis, Axis::Vertical);
+
+                    // Every row holds each of the stage's seams exactly once.
+                    for y in 0..size.y {
+                        let mut row = (0..size.x)
+                            .filter_map(|x| *pass.seams.get(Vector2::new(x, y)))
+                            .collect::<Vec<_>>();
+                        row.sort_unstable();
+                        assert_eq!(row, (0..pass.count).collect::<Vec<_>>());
+                    }
+                    stages.push((size.x, pass.count));
+                }
+                Ok(())
+            })
+            .run(image)
+            .unwrap();
+
+        assert_eq!(output.image.dimensions(), (75, 20));
+        assert_eq!(stages, vec![(30, 15), (45, 22), (67, 8)]);
+        assert_eq!(output.seam_count, 45);
+    }
+
+    #[test]
+    fn soft_removal_masks_remove_the_whole_object() {
+        use image::Rgba;
+
+        let object = Rgba([255, 0, 255, 255]);
+        let mut image = open_thumbnail(40, 30);
+        let is_object = |x: u32, y: u32| (10..14).contains(&x) && (5..25).contains(&y);
+        for (x, y, pixel) in image.enumerate_pixels_mut() {
+            if is_object(x, y) {
+                *pixel = object;
+            }
+        }
+
+        // The faintest possible grayscale mark, on an object capped by protected pixels which
+        // every seam through the object has to cross.
+        let mask = WeightImage::from_fn(40, 30, |x, y| {
+            Luma([if is_object(x, y) { 1. / 255. } else { 0. }])
+        });
+        let protect = WeightImage::from_fn(40, 30, |x, y| {
+            Luma([if (8..16).contains(&x) && !(5..25).contains(&y) {
+                1.
+            } else {
+                0.
+            }])
+        });
+
+        let mut removal_passes = 0;
+        let output = Carver::new()
+            .remove_mask(mask)
+            .protect_mask(protect)
+            .on_pass(|pass| {
+                if let Pass::Carve(CarvePass {
+                    phase: Phase::Removal,
+                    ..
+                }) = pass
+                {
+                    removal_passes += 1;
+                }
+                Ok(())
+            })
+            .run(image)
+            .unwrap();
+
+        assert!(output.image.pixels().all(|pixel| *pixel != object));
+        assert_eq!(removal_passes, 4);
+        assert_eq!(output.image.dimensions(), (36, 30));
+    }
+
+    #[test]
+    fn protected_columns_survive_carving() {
+        let image = open_thumbnail(40, 30);
+        let column = |image: &RgbaImage, x: u32| {
+            (0..image.height())
+                .map(|y| *image.get_pixel(x, y))
+                .collect::<Vec<_>>()
+        };
+
+        for protected in [0, 17, 39] {
+            let protect =
+                WeightImage::from_fn(40, 30, |x, _| Luma([if x == protected { 1. } else { 0. }]));
+            let output = Carver::new()
+                .size(Vector2::new(10, 30))
+                .protect_mask(protect)
+                .run(image.clone())
+                .unwrap();
+
+            let original = column(&image, protected);
+            assert!(
+                (0..10).any(|x| column(&output.image, x) == original),
+                "column {} was carved",
+                protected
+            );
+        }
+    }
+
+    #[test]
+    fn transparent_video_padding_is_carved_first() {
+        use crate::video::Video;
+        use image::{Delay, Rgba};
+
+        // Hide a checkerboard, which would otherwise be the most expensive part of every frame,
+        // behind transparent padding.
+        let frame = |shift: u32| {
+            let mut frame = open_thumbnail(30, 20);
+            for (x, y, pixel) in frame.enumerate_pixels_mut() {
+                if x >= 20 {
+                    let value = if (x + y + shift) % 2 == 0 { 255 } else { 0 };
+                    *pixel = Rgba([value, value, value, 0]);
+                }
+            }
+            frame
+        };
+        let mut video = Video {
+            frames: vec![frame(0), frame(1)],
+            delays: vec![Delay::from_numer_denom_ms(100, 1); 2],
+            first_index: 0,
+        };
+
+        Carver::new()
+            .size(Vector2::new(20, 20))
+            .run_video(&mut video, 0.)
+            .unwrap();
+
+        for frame in &video.frames {
+            assert_eq!(frame.dimensions(), (20, 20));
+            assert!(frame.pixels().all(|pixel| pixel.0[3] == 255));
+        }
+    }
+}
@@ -0,0 +1,803 @@
+//! Out-of-core carving for images which are too large to be held in memory. The pixels, energy and
+//! seam choices of the image are kept in scratch files, one line after the other, and only strips
+//! of consecutive lines are loaded at once. Since the weight of a seam passing through a line only
+//! depends on the line before it, every seam is found by a single pass from the first line to the
+//! last which stores the offset every pixel is best reached from, followed by a backtrack through
+//! those offsets from the last line to the first. A final pass carves the seam out of the pixels
+//! and the energy, recomputing the energy of the pixels around it.
+
+use crate::carver::{
+    cascade_cell, window_weight, Axis, EnergyMode, SeamFinder, SeamShape, CASCADE_CHUNK,
+};
+use crate::depth::ColorPixel;
+use crate::error::{CarveError, CarveResult, PathContext};
+use crate::resize::TargetSize;
+use crate::util::{rgba_to_vec4, ColorKernel, KernelRect, Timer};
+use cgmath::{Vector2, Vector4};
+use image::{ImageFormat, Rgba};
+use rayon::prelude::*;
+use std::convert::TryInto;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The memory budget used when none is specified.
+pub const DEFAULT_MEMORY_BUDGET: usize = 256 << 20;
+
+/// Parses a number of bytes with an optional binary `K`, `M` or `G` suffix (e.g. `512M`).
+pub fn parse_memory_size(arg: &str) -> Result<usize, String> {
+    const FORM_ERR: &str =
+        "Argument must be a number of bytes with an optional `K`, `M` or `G` suffix (e.g. `512M`).";
+
+    let (digits, shift) = match arg.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&arg[..i], 10),
+        Some((i, 'm' | 'M')) => (&arg[..i], 20),
+        Some((i, 'g' | 'G')) => (&arg[..i], 30),
+        _ => (arg, 0),
+    };
+
+    digits
+        .parse::<usize>()
+        .ok()
+        .and_then(|bytes| bytes.checked_mul(1 << shift))
+        .ok_or_else(|| FORM_ERR.to_string())
+}
+
+/// Shrinks PNG images which don't fit in memory by streaming them through scratch files. Seams are
+/// exactly those [Carver](crate::Carver) finds when removing every vertical seam before any
+/// horizontal one, but only the backward energy mode and energy functions with a finite
+/// [radius](crate::energy::EnergyFunction::radius) are supported. Images are carved at 8 or 16 bits
+/// per channel, whichever they are stored at.
+///
+/// Every seam costs a full pass over the scratch files, which take up 9 bytes per pixel of the
+/// image (13 bytes at 16 bits per channel).
+pub struct StreamCarver {
+    finder: SeamFinder,
+    size: Option<TargetSize>,
+    memory_budget: usize,
+    scratch_dir: PathBuf,
+    ignore_alpha: bool,
+}
+
+impl Default for StreamCarver {
+    fn default() -> Self {
+        Self {
+            finder: SeamFinder::default(),
+            size: None,
+            memory_budget: DEFAULT_MEMORY_BUDGET,
+            scratch_dir: std::env::temp_dir(),
+            ignore_alpha: false,
+        }
+    }
+}
+
+impl StreamCarver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The size of the output. Neither axis may grow. Defaults to the size of the input.
+    pub fn size(mut self, size: TargetSize) -> Self {
+        self.size = Some(size);
+        self
+    }
+
+    pub fn finder(mut self, finder: SeamFinder) -> Self {
+        self.finder = finder;
+        self
+    }
+
+    /// The number of bytes the carver may hold in memory at once. The larger the budget, the more
+    /// lines are read and written at once.
+    pub fn memory_budget(mut self, bytes: usize) -> Self {
+        self.memory_budget = bytes;
+        self
+    }
+
+    /// The directory in which scratch files are created. Defaults to the system's temporary
+    /// directory.
+    pub fn scratch_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.scratch_dir = dir.into();
+        self
+    }
+
+    /// See [Carver::ignore_alpha](crate::Carver::ignore_alpha).
+    pub fn ignore_alpha(mut self) -> Self {
+        self.ignore_alpha = true;
+        self
+    }
+
+    /// Carves the PNG image at `input` and saves the result as a PNG image at `output`.
+    pub fn run(&self, input: &Path, output: &Path) -> CarveResult<()> {
+        for path in [input, output] {
+            if !matches!(ImageFormat::from_path(path), Ok(ImageFormat::Png)) {
+                return Err(CarveError::InvalidInput(format!(
+                    "Only PNG images can be streamed. ({:?} isn't one)",
+                    path
+                )));
+            }
+        }
+
+        if self.finder.mode == EnergyMode::Forward {
+            return Err(CarveError::InvalidInput(
+                "Only the `backward` energy mode can be streamed.".to_string(),
+            ));
+        }
+
+        let radius = self.finder.radius().ok_or_else(|| {
+            CarveError::InvalidInput(
+                "Only energy functions which look at the neighborhood of every pixel can be \
+                 streamed, which rules out the saliency energies."
+                    .to_string(),
+            )
+        })?;
+        let radius = radius.max(1);
+
+        let (mut store, transparent) = LineStore::import(input, &self.scratch_dir)?;
+        let from_size = Vector2::new(store.across, store.along);
+        let to_size = self.size.map_or(from_size, |size| size.resolve(from_size));
+
+        if to_size.x <= 0 || to_size.y <= 0 || to_size.x > from_size.x || to_size.y > from_size.y {
+            return Err(CarveError::InvalidSize(format!(
+                "Streaming can only shrink images to a positive size. (wants resize from {}x{} to \
+                 {}x{})",
+                from_size.x, from_size.y, to_size.x, to_size.y
+            )));
+        }
+
+        let mut finder = self.finder.clone();
+        finder.alpha = transparent && !self.ignore_alpha;
+
+        if to_size.x < from_size.x {
+            let strip = self.strip_lines(&store, radius)?;
+            store.carve(
+                &finder,
+                from_size.x - to_size.x,
+                strip,
+                radius,
+                &self.scratch_dir,
+            )?;
+        }
+
+        if to_size.y < from_size.y {
+            let strip = self.strip_lines(&store, radius)?;
+            store = store.transpose(strip, &self.scratch_dir)?;
+
+            let strip = self.strip_lines(&store, radius)?;
+            store.carve(
+                &finder,
+                from_size.y - to_size.y,
+                strip,
+                radius,
+                &self.scratch_dir,
+            )?;
+            store = store.transpose(strip, &self.scratch_dir)?;
+        }
+
+        let strip = self.strip_lines(&store, radius)?;
+        store.export(output, strip)
+    }
+
+    /// The number of lines of `store` which can be loaded at once without exceeding the memory
+    /// budget.
+    fn strip_lines(&self, store: &LineStore, radius: i32) -> CarveResult<usize> {
+        let capacity = store.capacity();
+        let along = store.along as usize;
+        let pixel_size = store.pixel_size();
+
+        // Every line of a strip holds its pixels, energy and seam choices. On top of that, `radius`
+        // lines on either side of the strip are needed to compute its energy, and a few lines of
+        // seam weights and the position of the seam on every line are kept while finding a seam.
+        let per_line = capacity * (pixel_size + 5);
+        let fixed = 2 * radius as usize * capacity * pixel_size + capacity * 20 + along * 4;
+
+        if self.memory_budget < fixed + per_line {
+            return Err(CarveError::InvalidInput(format!(
+                "A memory budget of {} bytes is too small to stream this image. At least {} bytes \
+                 are needed.",
+                self.memory_budget,
+                fixed + per_line
+            )));
+        }
+        Ok(((self.memory_budget - fixed) / per_line).min(along))
+    }
+}
+
+/// A temporary file made of lines of `stride` bytes which is deleted once dropped.
+struct ScratchFile {
+    path: PathBuf,
+    file: File,
+    stride: usize,
+}
+
+impl ScratchFile {
+    fn create(dir: &Path, stride: usize) -> CarveResult<Self> {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+        let path = dir.join(format!(
+            "seam-carver-{}-{}.tmp",
+            std::process::id(),
+            NEXT_ID.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .writing(&path)?;
+
+        Ok(Self { path, file, stride })
+    }
+
+    /// Fills `buf` with the bytes starting `offset` bytes into the line `first`.
+    fn read_at(&mut self, first: usize, offset: usize, buf: &mut [u8]) -> CarveResult<()> {
+        self.file
+            .seek(SeekFrom::Start(
+                first as u64 * self.stride as u64 + offset as u64,
+            ))
+            .and_then(|_| self.file.read_exact(buf))
+            .reading(&self.path)
+    }
+
+    /// Fills `buf` with the lines starting at `first`. The length of `buf` must be a multiple of
+    /// the stride.
+    fn read_lines(&mut self, first: usize, buf: &mut [u8]) -> CarveResult<()> {
+        debug_assert_eq!(buf.len() % self.stride, 0);
+        self.read_at(first, 0, buf)
+    }
+
+    /// Writes `buf` to the file starting `offset` bytes into the line `first`.
+    fn write_at(&mut self, first: usize, offset: usize, buf: &[u8]) -> CarveResult<()> {
+        self.file
+            .seek(SeekFrom::Start(
+                first as u64 * self.stride as u64 + offset as u64,
+            ))
+            .and_then(|_| self.file.write_all(buf))
+            .writing(&self.path)
+    }
+
+    fn write_lines(&mut self, first: usize, buf: &[u8]) -> CarveResult<()> {
+        debug_assert_eq!(buf.len() % self.stride, 0);
+        self.write_at(first, 0, buf)
+    }
+}
+
+impl Drop for ScratchFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// An RGBA image held in a scratch file as lines running across `axis`, i.e. as rows while carving
+/// vertical seams and as columns while carving horizontal ones. Lines keep the length they had when
+/// the store was created and carving drops pixels from their end.
+struct LineStore {
+    pixels: ScratchFile,
+    /// The number of bytes of every channel, `1` or `2`. 16-bit channels are stored big-endian,
+    /// just like in the PNG image.
+    sample_size: usize,
+    axis: Axis,
+    /// The number of lines, which is the length of a seam.
+    along: i32,
+    /// The number of pixels left on every line.
+    across: i32,
+}
+
+/// A seam to carve out of a [LineStore].
+#[derive(Copy, Clone)]
+struct Carve<'a> {
+    /// The position of the seam on every line, from the first line to the last.
+    path: &'a [i32],
+    /// The number of pixels removed from every line. This may be less than the width of the seam's
+    /// shape when fewer seams are left to carve.
+    width: i32,
+    shape: SeamShape,
+}
+
+impl LineStore {
+    /// Decodes a PNG image one row at a time into a store of rows. Also returns whether any of its
+    /// pixels is transparent.
+    fn import(path: &Path, dir: &Path) -> CarveResult<(Self, bool)> {
+        let _timer = Timer::start("stream_import");
+        let file = File::open(path).reading(path)?;
+        // The decoder strips 16-bit samples down to 8 bits unless told otherwise.
+        let mut decoder = png::Decoder::new(BufReader::new(file));
+        decoder.set_transformations(png::Transformations::EXPAND);
+        let (info, mut reader) = decoder.read_info().reading(path)?;
+
+        // The rows of interlaced images are only complete once the whole image has been decoded.
+        if reader.info().interlaced {
+            return Err(CarveError::InvalidInput(format!(
+                "Interlaced PNG images can't be streamed. ({:?} is interlaced)",
+                path
+            )));
+        }
+
+        // Palettes and low bit depths are expanded by the decoder, so samples are either 8 or 16
+        // bits wide. Both are stored as they are.
+        let (color, depth) = reader.output_color_type();
+        let sample_size = if depth == png::BitDepth::Sixteen {
+            2
+        } else {
+            1
+        };
+        let opaque = [u8::MAX; 2];
+        let opaque = &opaque[..sample_size];
+
+        let pixels = ScratchFile::create(dir, info.width as usize * 4 * sample_size)?;
+        let mut writer = BufWriter::new(&pixels.file);
+        let mut line = Vec::with_capacity(pixels.stride);
+        let mut transparent = false;
+
+        while let Some(row) = reader.next_row().reading(path)? {
+            line.clear();
+            for pixel in row.chunks_exact(color.samples() * sample_size) {
+                let sample = |i: usize| &pixel[i * sample_size..(i + 1) * sample_size];
+                let rgba = match color {
+                    png::ColorType::Grayscale => [sample(0), sample(0), sample(0), opaque],
+                    png::ColorType::GrayscaleAlpha => [sample(0), sample(0), sample(0), sample(1)],
+                    png::ColorType::RGB => [sample(0), sample(1), sample(2), opaque],
+                    png::ColorType::RGBA => [sample(0), sample(1), sample(2), sample(3)],
+                    png::ColorType::Indexed => unreachable!(),
+                };
+                transparent |= rgba[3] != opaque;
+                for sample in rgba {
+                    line.extend_from_slice(sample);
+                }
+            }
+            writer.write_all(&line).writing(&pixels.path)?;
+        }
+        writer.flush().writing(&pixels.path)?;
+        drop(writer);
+
+        let store = Self {
+            pixels,
+            sample_size,
+            axis: Axis::Vertical,
+            along: info.height as i32,
+            across: info.width as i32,
+        };
+        Ok((store, transparent))
+    }
+
+    /// Encodes the store, which must hold rows, as a PNG image one row at a time.
+    fn export(&mut self, path: &Path, strip: usize) -> CarveResult<()> {
+        let _timer = Timer::start("stream_export");
+        debug_assert_eq!(self.axis, Axis::Vertical);
+
+        let file = File::create(path).writing(path)?;
+        let mut out = BufWriter::new(file);
+        {
+            let mut encoder = png::Encoder::new(&mut out, self.across as u32, self.along as u32);
+            encoder.set_color(png::ColorType::RGBA);
+            encoder.set_depth(match self.sample_size {
+                1 => png::BitDepth::Eight,
+                _ => png::BitDepth::Sixteen,
+            });
+            let mut writer = encoder.write_header().writing(path)?;
+            let mut stream = writer.stream_writer();
+
+            let stride = self.pixels.stride;
+            let mut lines = vec![0; strip * stride];
+            for first in (0..self.along as usize).step_by(strip) {
+                let count = strip.min(self.along as usize - first);
+                self.pixels
+                    .read_lines(first, &mut lines[..count * stride])?;
+                for line in lines[..count * stride].chunks_exact(stride) {
+                    stream
+                        .write_all(&line[..self.across as usize * self.pixel_size()])
+                        .writing(path)?;
+                }
+            }
+
+            // The image data is only terminated once the stream and the writer are dropped.
+        }
+        out.flush().writing(path)
+    }
+
+    /// The number of bytes of every pixel.
+    fn pixel_size(&self) -> usize {
+        4 * self.sample_size
+    }
+
+    /// The number of pixels every line has room for.
+    fn capacity(&self) -> usize {
+        self.pixels.stride / self.pixel_size()
+    }
+
+    /// Stores the image as lines running across the other axis, holding as much of it in memory
+    /// at once as `strip` lines of the store take up.
+    fn transpose(mut self, strip: usize, dir: &Path) -> CarveResult<Self> {
+        let _timer = Timer::start("stream_transpose");
+        let pixel_size = self.pixel_size();
+        let along = self.along as usize;
+        let across = self.across as usize;
+        let mut transposed = ScratchFile::create(dir, along * pixel_size)?;
+
+        // The transposed lines are assembled a tile at a time, from the segment of every line of
+        // the store which they cross, and written out in one go.
+        let tile = (strip * self.pixels.stride / transposed.stride).clamp(1, across);
+        let mut lines = vec![0; tile * transposed.stride];
+        let mut segment = vec![0; tile * pixel_size];
+
+        for first in (0..across).step_by(tile) {
+            let count = tile.min(across - first);
+            let segment = &mut segment[..count * pixel_size];
+            for line in 0..along {
+                self.pixels.read_at(line, first * pixel_size, segment)?;
+                for (i, pixel) in segment.chunks_exact(pixel_size).enumerate() {
+                    let at = i * transposed.stride + line * pixel_size;
+                    lines[at..at + pixel_size].copy_from_slice(pixel);
+                }
+            }
+            transposed.write_lines(first, &lines[..count * transposed.stride])?;
+        }
+
+        Ok(Self {
+            pixels: transposed,
+            sample_size: self.sample_size,
+            axis: match self.axis {
+                Axis::Vertical => Axis::Horizontal,
+                Axis::Horizontal => Axis::Vertical,
+            },
+            along: self.across,
+            across: self.along,
+        })
+    }
+
+    /// Carves `count` seams running along the store's axis out of the image.
+    fn carve(
+        &mut self,
+        finder: &SeamFinder,
+        count: i32,
+        strip: usize,
+        radius: i32,
+        dir: &Path,
+    ) -> CarveResult<()> {
+        let _timer = Timer::start("stream_carve");
+        let mut energy = ScratchFile::create(dir, self.capacity() * 4)?;
+        let mut choices = ScratchFile::create(dir, self.capacity())?;
+        self.update_energy(finder, &mut energy, None, strip, radius)?;
+
+        let mut remaining = count;
+        while remaining > 0 {
+            let shape = finder.shape.fit(self.across);
+            let path = self.find_seam(&mut energy, &mut choices, shape, strip)?;
+            let carve = Carve {
+                path: &path,
+                width: shape.width.min(remaining),
+                shape,
+            };
+            self.update_energy(finder, &mut energy, Some(carve), strip, radius)?;
+            remaining -= carve.width;
+        }
+        Ok(())
+    }
+
+    /// Cascades the seam weights from the first line to the last, storing the offset every pixel
+    /// is best reached from in `choices`, and traces the lowest weighted seam back through them.
+    /// Returns the position of the seam on every line.
+    fn find_seam(
+        &mut self,
+        energy: &mut ScratchFile,
+        choices: &mut ScratchFile,
+        shape: SeamShape,
+        strip: usize,
+    ) -> CarveResult<Vec<i32>> {
+        let _timer = Timer::start("stream_find");
+        let along = self.along as usize;
+        let across = self.across;
+
+        // The cascaded weights of the previous line, which the first line doesn't have.
+        let mut prev = Vec::<f32>::new();
+        let mut weights = Vec::with_capacity(across as usize);
+        let mut cascaded = Vec::new();
+        let mut energy_lines = vec![0; strip * energy.stride];
+        let mut choice_lines = vec![0; strip * choices.stride];
+
+        for first in (0..along).step_by(strip) {
+            let count = strip.min(along - first);
+            energy.read_lines(first, &mut energy_lines[..count * energy.stride])?;
+
+            for (i, line) in energy_lines[..count * energy.stride]
+                .chunks_exact(energy.stride)
+                .enumerate()
+            {
+                weights.clear();
+                weights.extend(
+                    line[..across as usize * 4]
+                        .chunks_exact(4)
+                        .map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap())),
+                );
+                if shape.width > 1 {
+                    let energies = std::mem::take(&mut weights);
+                    let energy = |pos: Vector2<i32>| energies[pos.x as usize];
+                    weights.extend((0..across).map(|x| {
+                        window_weight(
+                            energy,
+                            Axis::Vertical,
+                            shape.width,
+                            across,
+                            Vector2::new(x, 0),
+                        )
+                    }));
+                }
+
+                // Lines are cascaded in their own coordinates, with `x` running across them.
+                let prev_ref = &prev;
+                let prev_weight = |x: i32| prev_ref.get(usize::try_from(x).ok()?).copied();
+                let weights = &weights;
+                (0..across)
+                    .into_par_iter()
+                    .with_min_len(CASCADE_CHUNK)
+                    .map(|x| {
+                        let pos = Vector2::new(x, (first + i) as i32);
+                        let transition = |_, _| 0.;
+                        let weight = weights[x as usize];
+                        cascade_cell(
+                            prev_weight,
+                            Axis::Vertical,
+                            shape.connectivity,
+                            pos,
+                            weight,
+                            &transition,
+                        )
+                    })
+                    .collect_into_vec(&mut cascaded);
+
+                let choice_line = &mut choice_lines[i * choices.stride..];
+                for (choice, (rel, _)) in choice_line.iter_mut().zip(&cascaded) {
+                    *choice = *rel as u8;
+                }
+                prev.clear();
+                prev.extend(cascaded.iter().map(|(_, weight)| *weight));
+            }
+            choices.write_lines(first, &choice_lines[..count * choices.stride])?;
+        }
+
+        let (best, _) = prev
+            .iter()
+            .enumerate()
+            .min_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap())
+            .unwrap();
+
+        // Follow the choices back from the last line to the first.
+        let mut path = vec![0; along];
+        let mut x = best as i32;
+        for first in (0..along).step_by(strip).rev() {
+            let count = strip.min(along - first);
+            choices.read_lines(first, &mut choice_lines[..count * choices.stride])?;
+            for i in (0..count).rev() {
+                path[first + i] = x;
+                x += choice_lines[i * choices.stride + x as usize] as i8 as i32;
+            }
+        }
+        Ok(path)
+    }
+
+    /// Carves `carve` out of the pixels and energy of the image and recomputes the energy of the
+    /// pixels whose neighborhood changed, just like [SeamCache](crate::carver::SeamCache) does.
+    /// Without a seam, the energy of every pixel is computed from scratch.
+    fn update_energy(
+        &mut self,
+        finder: &SeamFinder,
+        energy: &mut ScratchFile,
+        carve: Option<Carve>,
+        strip: usize,
+        radius: i32,
+    ) -> CarveResult<()> {
+        let _timer = Timer::start("stream_update_energy");
+        let along = self.along as usize;
+        let stride = self.pixels.stride;
+        let pixel_size = self.pixel_size();
+        let old_across = self.across as usize;
+        let across = self.across - carve.map_or(0, |carve| carve.width);
+        let context = radius as usize;
+
+        // Removes the carved pixels from a line whose pixels are `size` bytes wide.
+        let carve_line = |line: &mut [u8], along: usize, size: usize| {
+            if let Some(carve) = carve {
+                let at = carve.path[along] as usize * size;
+                line.copy_within(at + carve.width as usize * size..old_across * size, at);
+            }
+        };
+
+        // The carved lines around the current strip, starting at the line `window_first`. Lines
+        // are carved and written back as soon as they are loaded.
+        let mut window = Vec::new();
+        let mut window_first = 0;
+        let mut loaded = 0;
+        let mut energy_lines = vec![0; strip * energy.stride];
+
+        for first in (0..along).step_by(strip) {
+            let count = strip.min(along - first);
+
+            let keep = first.saturating_sub(context);
+            window.drain(..(keep - window_first) * stride);
+            window_first = keep;
+
+            let end = (first + count + context).min(along);
+            if end > loaded {
+                let start = window.len();
+                window.resize(start + (end - loaded) * stride, 0);
+                self.pixels.read_lines(loaded, &mut window[start..])?;
+                if carve.is_some() {
+                    for (i, line) in window[start..].chunks_exact_mut(stride).enumerate() {
+                        carve_line(line, loaded + i, pixel_size);
+                    }
+                    self.pixels.write_lines(loaded, &window[start..])?;
+                }
+                loaded = end;
+            }
+
+            let lines = &mut energy_lines[..count * energy.stride];
+            if carve.is_some() {
+                energy.read_lines(first, lines)?;
+            }
+
+            let view = Strip {
+                pixels: &window,
+                stride,
+                sample_size: self.sample_size,
+                axis: self.axis,
+                lines: (loaded - window_first) as i32,
+                across,
+            };
+            lines
+                .par_chunks_mut(energy.stride)
+                .enumerate()
+                .for_each(|(i, line)| {
+                    let along = first + i;
+                    carve_line(line, along, 4);
+
+                    // See `SeamCache::carve` for the reach of a seam.
+                    let dirty = match carve {
+                        Some(carve) => {
+                            let reach = (carve.shape.connectivity + 1) * radius;
+                            let at = carve.path[along];
+                            (at - reach - carve.shape.width).max(0)..(at + reach + 1).min(across)
+                        }
+                        None => 0..across,
+                    };
+
+                    for x in dirty {
+                        let pos = self.axis.pos((along - window_first) as i32, x);
+                        let weight = finder.weight_at(&view, self.axis, pos);
+                        line[x as usize * 4..x as usize * 4 + 4]
+                            .copy_from_slice(&weight.to_le_bytes());
+                    }
+                });
+            energy.write_lines(first, lines)?;
+        }
+
+        self.across = across;
+        Ok(())
+    }
+}
+
+/// Consecutive lines of a [LineStore] held in memory, seen in the orientation of the image.
+struct Strip<'a> {
+    pixels: &'a [u8],
+    stride: usize,
+    sample_size: usize,
+    axis: Axis,
+    lines: i32,
+    across: i32,
+}
+
+impl KernelRect for Strip<'_> {
+    fn size(&self) -> Vector2<i32> {
+        self.axis.pos(self.lines, self.across)
+    }
+}
+
+impl ColorKernel for Strip<'_> {
+    fn color(&self, pos: Vector2<i32>) -> Vector4<f32> {
+        let (along, across) = self.axis.split(pos);
+        let at = along as usize * self.stride + across as usize * 4 * self.sample_size;
+        match self.sample_size {
+            1 => rgba_to_vec4(&Rgba(self.pixels[at..at + 4].try_into().unwrap())),
+            _ => {
+                let sample = |i: usize| {
+                    u16::from_be_bytes([self.pixels[at + 2 * i], self.pixels[at + 2 * i + 1]])
+                };
+                Rgba([sample(0), sample(1), sample(2), sample(3)]).to_vec4()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::energy::energy_from_name;
+    use crate::Carver;
+    use image::imageops::thumbnail;
+    use image::DynamicImage;
+
+    #[test]
+    fn streaming_matches_carving_in_memory() {
+        let path = format!("{}/images/cat.png", env!("CARGO_MANIFEST_DIR"));
+        let image = thumbnail(&image::open(path).unwrap().into_rgba8(), 48, 40);
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("input.png");
+        image.save(&input).unwrap();
+
+        let shapes = [
+            ("derivative", SeamShape::default()),
+            (
+                "sobel",
+                SeamShape {
+                    connectivity: 2,
+                    width: 3,
+                },
+            ),
+        ];
+        for (energy, shape) in shapes {
+            let finder = SeamFinder {
+                energy: energy_from_name(energy, None).unwrap(),
+                shape,
+                ..Default::default()
+            };
+            let expected = Carver::new()
+                .finder(finder.clone())
+                .size(Vector2::new(37, 31))
+                .run(image.clone())
+                .unwrap()
+                .image;
+
+            // Only a few lines fit in the budget so the image goes through many strips.
+            let output = dir.path().join(format!("{}.png", energy));
+            StreamCarver::new()
+                .finder(finder)
+                .size("37x31".parse().unwrap())
+                .memory_budget(3000)
+                .scratch_dir(dir.path())
+                .run(&input, &output)
+                .unwrap();
+            assert_eq!(image::open(&output).unwrap().into_rgba8(), expected);
+        }
+
+        let too_small = StreamCarver::new()
+            .memory_budget(1000)
+            .scratch_dir(dir.path())
+            .run(&input, &dir.path().join("too-small.png"));
+        assert!(matches!(too_small, Err(CarveError::InvalidInput(_))));
+    }
+
+    #[test]
+    fn sixteen_bit_images_are_streamed_at_their_depth() {
+        let path = format!("{}/images/cat.png", env!("CARGO_MANIFEST_DIR"));
+        let image =
+            DynamicImage::ImageRgba8(thumbnail(&image::open(path).unwrap().into_rgba8(), 48, 40));
+        // Spread the samples over the full 16-bit range so that no value is a multiple of 257.
+        let mut image = image.into_rgba16();
+        for (x, y, pixel) in image.enumerate_pixels_mut() {
+            for sample in &mut pixel.0[..3] {
+                *sample = sample.saturating_add(((x * 7 + y * 13) % 200) as u16);
+            }
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("input.png");
+        image.save(&input).unwrap();
+
+        let expected = Carver::new()
+            .size(Vector2::new(37, 31))
+            .run(image)
+            .unwrap()
+            .image;
+
+        let output = dir.path().join("output.png");
+        StreamCarver::new()
+            .size("37x31".parse().unwrap())
+            .memory_budget(6000)
+            .scratch_dir(dir.path())
+            .run(&input, &output)
+            .unwrap();
+        match image::open(&output).unwrap() {
+            DynamicImage::ImageRgba16(output) => assert_eq!(output, expected),
+            output => panic!("expected a 16-bit RGBA image, got {:?}", output.color()),
+        }
+    }
+}
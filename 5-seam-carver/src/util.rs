@@ -1,6 +1,9 @@
-use cgmath::{Vector2, Vector4};
+use crate::depth::ColorPixel;
+use cgmath::{Vector2, Vector3, Vector4};
 use image::{ImageBuffer, Luma, Pixel, Rgba, RgbaImage};
 use lazy_static::lazy_static;
+use palette::{IntoColor, Lab, Oklab, Srgb};
+use rayon::prelude::*;
 use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
 use std::mem::MaybeUninit;
@@ -178,6 +181,46 @@ impl<P: 'static + Default + Copy> Kernel for VecKernel<P> {
     }
 }
 
+impl<P> VecKernel<P> {
+    pub fn pixels(&self) -> &[P] {
+        &self.pixels
+    }
+
+    /// The parallel counterpart of [Kernel::from_fn]. See [par_pixels].
+    pub fn par_from_fn<F>(size: Vector2<i32>, handler: F) -> Self
+    where
+        P: Send,
+        F: Fn(Vector2<i32>) -> P + Sync,
+    {
+        Self {
+            width: size.x as u32,
+            pixels: par_pixels(size, handler),
+        }
+    }
+}
+
+/// Computes every pixel of a kernel of the given size in parallel, one row per task, and returns
+/// them in row-major order.
+pub fn par_pixels<P, F>(size: Vector2<i32>, handler: F) -> Vec<P>
+where
+    P: Send,
+    F: Fn(Vector2<i32>) -> P + Sync,
+{
+    let handler = &handler;
+    (0..size.y)
+        .into_par_iter()
+        .flat_map_iter(|y| (0..size.x).map(move |x| handler(Vector2::new(x, y))))
+        .collect()
+}
+
+/// The parallel counterpart of [Kernel::from_fn] for weight images. See [par_pixels].
+pub fn par_weight_image<F>(size: Vector2<i32>, handler: F) -> WeightImage
+where
+    F: Fn(Vector2<i32>) -> f32 + Sync,
+{
+    WeightImage::from_raw(size.x as u32, size.y as u32, par_pixels(size, handler)).unwrap()
+}
+
 impl<P: 'static + Default + Copy> KernelRect for VecKernel<P> {
     fn size(&self) -> Vector2<i32> {
         Vector2::new(
@@ -187,6 +230,46 @@ impl<P: 'static + Default + Copy> KernelRect for VecKernel<P> {
     }
 }
 
+/// An object-safe view of an image which exposes the color of its pixels as a vector whose
+/// components lie between `0` and `1`. This lets energy functions read any kind of kernel.
+pub trait ColorKernel: KernelRect + Sync {
+    fn color(&self, pos: Vector2<i32>) -> Vector4<f32>;
+
+    fn try_color(&self, pos: Vector2<i32>) -> Option<Vector4<f32>> {
+        if self.contains_pos(pos) {
+            Some(self.color(pos))
+        } else {
+            None
+        }
+    }
+}
+
+impl<K: Kernel + Sync> ColorKernel for K
+where
+    K::Pixel: ColorPixel,
+{
+    fn color(&self, pos: Vector2<i32>) -> Vector4<f32> {
+        self.get(pos).to_vec4()
+    }
+}
+
+/// A view of an image whose colors are premultiplied by their alpha, so that the (often arbitrary)
+/// color of transparent pixels is invisible.
+pub struct Premultiplied<'a>(pub &'a dyn ColorKernel);
+
+impl KernelRect for Premultiplied<'_> {
+    fn size(&self) -> Vector2<i32> {
+        self.0.size()
+    }
+}
+
+impl ColorKernel for Premultiplied<'_> {
+    fn color(&self, pos: Vector2<i32>) -> Vector4<f32> {
+        let color = self.0.color(pos);
+        (color.truncate() * color.w).extend(color.w)
+    }
+}
+
 // === Color magic === //
 
 pub fn luma_to_rgba(target: &WeightImage) -> RgbaImage {
@@ -206,6 +289,10 @@ pub fn luma_to_rgba(target: &WeightImage) -> RgbaImage {
     image
 }
 
+pub fn rgba_to_vec4(pixel: &Rgba<u8>) -> Vector4<f32> {
+    Vector4::from(pixel.0).cast::<f32>().unwrap() / u8::MAX as f32
+}
+
 pub fn vec4_to_rgba(vec: Vector4<f32>) -> Rgba<u8> {
     Rgba([
         (vec.x * 256.) as u8,
@@ -215,6 +302,20 @@ pub fn vec4_to_rgba(vec: Vector4<f32>) -> Rgba<u8> {
     ])
 }
 
+/// Converts a gamma-encoded sRGB color whose components lie between `0` and `1` into CIELAB under
+/// the D65 white point. `L` lies between `0` and `100`.
+pub fn srgb_to_lab(color: Vector3<f32>) -> Vector3<f32> {
+    let lab: Lab = Srgb::new(color.x, color.y, color.z).into_color();
+    Vector3::new(lab.l, lab.a, lab.b)
+}
+
+/// Converts a gamma-encoded sRGB color whose components lie between `0` and `1` into Björn
+/// Ottosson's Oklab. `L` lies between `0` and `1`.
+pub fn srgb_to_oklab(color: Vector3<f32>) -> Vector3<f32> {
+    let oklab: Oklab = Srgb::new(color.x, color.y, color.z).into_color();
+    Vector3::new(oklab.l, oklab.a, oklab.b)
+}
+
 // === Iterator magic === //
 
 #[derive(Debug)]
@@ -231,8 +332,8 @@ pub trait IterCollectArrayExt: Sized + IntoIterator {
         let mut target =
             unsafe { MaybeUninit::<[MaybeUninit<Self::Item>; N]>::uninit().assume_init() };
 
-        for i in 0..N {
-            target[i] = match iter.next() {
+        for (i, slot) in target.iter_mut().enumerate() {
+            *slot = match iter.next() {
                 Some(elem) => MaybeUninit::new(elem),
                 None => return Err(CollectArrayError::TooSmall(i)),
             }
@@ -270,21 +371,8 @@ where
     type Err = E;
 
     fn try_collect<C: FromIterator<Self::Ok>>(self) -> Result<C, Self::Err> {
-        let mut iter = self.into_iter();
-        let ok_iter = (&mut iter)
-            .take_while(Result::is_ok)
-            // Default unwrap methods format the error on panic.
-            .map(|res| match res {
-                Ok(res) => res,
-                Err(_) => unreachable!(),
-            });
-
-        let collection = ok_iter.collect::<C>();
-        match iter.next() {
-            Some(Ok(_)) => unreachable!(),
-            Some(Err(err)) => Err(err),
-            None => Ok(collection),
-        }
+        // Stops at the first error.
+        self.into_iter().collect()
     }
 }
 
@@ -317,7 +405,11 @@ impl<T> VecRemoveExt for Vec<T> {
                 let elem = elem as *mut T;
 
                 if should_keep {
-                    (self.get_unchecked_mut(write_at) as *mut T).copy_from_nonoverlapping(elem, 1);
+                    // Kept elements which haven't moved yet are already in place.
+                    if write_at != read_head {
+                        (self.get_unchecked_mut(write_at) as *mut T)
+                            .copy_from_nonoverlapping(elem, 1);
+                    }
                     write_at += 1;
                 } else {
                     // Otherwise, drop whatever is underneath the read head so the write head doesn't
@@ -0,0 +1,487 @@
+use crate::carved::CarvedKernel;
+use crate::carver::{Axis, Masks, SeamCache, SeamFinder, SeamGuide};
+use crate::error::{CarveError, CarveResult, PathContext};
+use crate::util::{luma_to_rgba, vec4_to_rgba, ColorKernel, Kernel, Timer, WeightImage};
+use cgmath::Vector2;
+use image::gif::{GifDecoder, GifEncoder, Repeat};
+use image::{open, AnimationDecoder, Delay, Frame, Rgba, RgbaImage};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+/// The delay between frames used when the input doesn't specify one (i.e. frame sequences).
+pub const DEFAULT_FRAME_DELAY_MS: u32 = 40;
+
+/// The default strength of the penalty keeping every seam close to its counterpart in the previous
+/// frame. See [SeamGuide::strength].
+pub const DEFAULT_COHERENCE: f32 = 0.02;
+
+/// A sequence of equally sized frames.
+#[derive(Debug, Clone)]
+pub struct Video {
+    pub frames: Vec<RgbaImage>,
+    pub delays: Vec<Delay>,
+    /// The index of the first frame of a numbered frame sequence. Saved sequences are numbered
+    /// starting from this index too.
+    pub first_index: usize,
+}
+
+/// Checks whether `path` refers to a video, i.e. an animated GIF or a numbered frame sequence.
+pub fn is_video_path(path: &str) -> bool {
+    is_frame_pattern(path) || is_gif_path(path)
+}
+
+fn is_gif_path(path: &str) -> bool {
+    Path::new(path)
+        .extension()
+        .map_or(false, |ext| ext.eq_ignore_ascii_case("gif"))
+}
+
+/// Checks whether `path` contains a `%d` or `%0Nd` frame number placeholder.
+pub fn is_frame_pattern(path: &str) -> bool {
+    split_frame_pattern(path).is_some()
+}
+
+/// Splits a frame pattern into the text before its placeholder, the zero-padded width of the frame
+/// number, and the text after its placeholder.
+fn split_frame_pattern(path: &str) -> Option<(&str, usize, &str)> {
+    let start = path.find('%')?;
+    let rest = &path[start + 1..];
+    let digits = rest.find(|c: char| !c.is_ascii_digit())?;
+    if !rest[digits..].starts_with('d') || (digits > 0 && !rest.starts_with('0')) {
+        return None;
+    }
+
+    let width = if digits > 0 {
+        rest[..digits].parse().ok()?
+    } else {
+        0
+    };
+    Some((&path[..start], width, &rest[digits + 1..]))
+}
+
+/// Substitutes `index` into the placeholder of a frame pattern.
+pub fn frame_path(pattern: &str, index: usize) -> String {
+    let (prefix, width, suffix) =
+        split_frame_pattern(pattern).expect("`pattern` is not a frame pattern!");
+    format!("{}{:0width$}{}", prefix, index, suffix, width = width)
+}
+
+/// Loads an animated GIF or a frame sequence. Sequences may start at either frame `0` or `1` and
+/// end at the first missing frame.
+pub fn load_video(path: &str) -> CarveResult<Video> {
+    let _timer = Timer::start("load_video");
+
+    let video = if is_frame_pattern(path) {
+        let first_index = (0..=1)
+            .find(|index| Path::new(&frame_path(path, *index)).exists())
+            .ok_or_else(|| {
+                CarveError::InvalidInput(format!("No frames match the pattern {:?}.", path))
+            })?;
+
+        let mut frames = Vec::new();
+        for index in first_index.. {
+            let frame_path = frame_path(path, index);
+            if !Path::new(&frame_path).exists() {
+                break;
+            }
+
+            let frame = open(&frame_path).reading(&frame_path)?;
+            frames.push(frame.into_rgba8());
+        }
+
+        Video {
+            delays: vec![Delay::from_numer_denom_ms(DEFAULT_FRAME_DELAY_MS, 1); frames.len()],
+            frames,
+            first_index,
+        }
+    } else {
+        let file = File::open(path).reading(path)?;
+        let decoder = GifDecoder::new(BufReader::new(file))
+            .and_then(|decoder| decoder.into_frames().collect_frames())
+            .reading(path)?;
+
+        let (frames, delays) = decoder
+            .into_iter()
+            .map(|frame| {
+                let delay = frame.delay();
+                (frame.into_buffer(), delay)
+            })
+            .unzip::<_, _, Vec<_>, Vec<_>>();
+
+        if frames.is_empty() {
+            return Err(CarveError::InvalidInput(format!(
+                "GIF {:?} has no frames.",
+                path
+            )));
+        }
+
+        Video {
+            frames,
+            delays,
+            first_index: 0,
+        }
+    };
+
+    let size = video.frames[0].dimensions();
+    if let Some(index) = video
+        .frames
+        .iter()
+        .position(|frame| frame.dimensions() != size)
+    {
+        return Err(CarveError::InvalidInput(format!(
+            "Frame {} of {:?} does not have the same dimensions as the first frame.",
+            video.first_index + index,
+            path
+        )));
+    }
+
+    Ok(video)
+}
+
+/// Saves a video as an animated GIF if `path` ends with `.gif` or as a frame sequence otherwise.
+pub fn save_video(path: &str, video: &Video) -> CarveResult<()> {
+    let _timer = Timer::start("save_video");
+
+    if is_frame_pattern(path) {
+        for (index, frame) in video.frames.iter().enumerate() {
+            let frame_path = frame_path(path, video.first_index + index);
+            frame.save(&frame_path).writing(&frame_path)?;
+        }
+    } else if is_gif_path(path) {
+        let frames = video
+            .frames
+            .iter()
+            .zip(&video.delays)
+            .map(|(frame, delay)| Frame::from_parts(frame.clone(), 0, 0, *delay));
+
+        let mut encoder = GifEncoder::new(File::create(path).writing(path)?);
+        encoder
+            .set_repeat(Repeat::Infinite)
+            .and_then(|_| encoder.encode_frames(frames))
+            .writing(path)?;
+    } else {
+        return Err(CarveError::InvalidInput(format!(
+            "Video output {:?} must either be a GIF or contain a `%d` frame number placeholder.",
+            path
+        )));
+    }
+
+    Ok(())
+}
+
+/// Carves every frame of a video with the same sequence of seam axes. Unless `coherence` is zero,
+/// every seam is guided towards the seam carved at the same step of the previous frame so that
+/// seams don't jump around between frames and the output doesn't flicker.
+pub fn carve_video(
+    video: &mut Video,
+    finder: &SeamFinder,
+    steps: &[Axis],
+    masks: &Masks,
+    coherence: f32,
+) {
+    let mut prev_seams = Vec::<Vec<i32>>::new();
+
+    for frame in &mut video.frames {
+        let _timer = Timer::start("carve_frame");
+
+        let guide = |step: usize| {
+            prev_seams
+                .get(step)
+                .filter(|_| coherence > 0.)
+                .map(|seam| SeamGuide {
+                    axis: steps[step],
+                    seam: seam.clone(),
+                    strength: coherence,
+                })
+        };
+
+        let mut image = CarvedKernel::from(std::mem::take(frame));
+        let mut masks = masks.clone();
+        let mut cache: Option<SeamCache> = None;
+        let mut seams = Vec::with_capacity(steps.len());
+        masks.guide = guide(0);
+
+        for (step, axis) in steps.iter().copied().enumerate() {
+            let cache = match &mut cache {
+                Some(cache) if cache.axis() == axis => cache,
+                cache => cache.insert(SeamCache::new(finder, &image, axis, &masks)),
+            };
+
+            // Seams are iterated from the last line to the first but guides are indexed by line.
+            let seam = cache.seam();
+            let mut positions = seam.iter().collect::<Vec<_>>();
+            positions.reverse();
+            seams.push(positions);
+
+            image.carve(axis, seam.iter());
+            masks.carve(seam);
+            masks.guide = guide(step + 1);
+            if step + 1 < steps.len() {
+                cache.carve(finder, &image, &masks);
+            }
+        }
+
+        *frame = image.into_inner();
+        prev_seams = seams;
+    }
+}
+
+/// Records the carving process into an animated GIF, one frame per recorded pass. Every frame
+/// shows the current image with the seams about to be carved or inserted highlighted and,
+/// optionally, the image's energy next to it.
+pub struct AnimationRecorder {
+    path: PathBuf,
+    encoder: GifEncoder<File>,
+    canvas: Vector2<i32>,
+    every: usize,
+    energy_panel: bool,
+    passes: usize,
+}
+
+impl AnimationRecorder {
+    /// The delay between the frames of the carving passes.
+    pub const FRAME_DELAY_MS: u32 = 50;
+
+    /// How long the final image is shown before the animation loops.
+    pub const FINAL_DELAY_MS: u32 = 2000;
+
+    /// The color of the highlighted seams.
+    pub const SEAM_COLOR: Rgba<u8> = Rgba([255, 0, 0, 255]);
+
+    /// The color of the canvas around the image once it has shrunk.
+    pub const BACKGROUND_COLOR: Rgba<u8> = Rgba([32, 32, 32, 255]);
+
+    /// Creates a recorder writing to `path`. `canvas` must be large enough to fit the image at
+    /// every step and only every `every`th pass is recorded.
+    pub fn new(
+        path: &str,
+        canvas: Vector2<i32>,
+        every: usize,
+        energy_panel: bool,
+    ) -> CarveResult<Self> {
+        if every == 0 {
+            return Err(CarveError::InvalidInput(
+                "Animations record every `n`th pass, so `n` must be at least 1.".to_string(),
+            ));
+        }
+
+        let file = File::create(path).writing(path)?;
+
+        // Quantizing every frame at the highest quality is very slow and these frames are only
+        // meant for debugging.
+        let mut encoder = GifEncoder::new_with_speed(file, 10);
+        encoder.set_repeat(Repeat::Infinite).writing(path)?;
+
+        Ok(Self {
+            path: PathBuf::from(path),
+            encoder,
+            canvas,
+            every,
+            energy_panel,
+            passes: 0,
+        })
+    }
+
+    /// Records a pass over `image` with the pixels of `seams` highlighted. `energy` is only called
+    /// if the pass is recorded and the recorder has an energy panel.
+    pub fn record<E, S>(&mut self, image: &dyn ColorKernel, energy: E, seams: S) -> CarveResult<()>
+    where
+        E: FnOnce() -> WeightImage,
+        S: IntoIterator<Item = Vector2<i32>>,
+    {
+        self.passes += 1;
+        if (self.passes - 1) % self.every != 0 {
+            return Ok(());
+        }
+
+        self.encode(image, energy, seams, Self::FRAME_DELAY_MS)
+    }
+
+    /// Records the final image, which is shown for longer than the other frames.
+    pub fn finish<E>(mut self, image: &dyn ColorKernel, energy: E) -> CarveResult<()>
+    where
+        E: FnOnce() -> WeightImage,
+    {
+        self.encode(image, energy, [], Self::FINAL_DELAY_MS)
+    }
+
+    fn encode<E, S>(
+        &mut self,
+        image: &dyn ColorKernel,
+        energy: E,
+        seams: S,
+        delay_ms: u32,
+    ) -> CarveResult<()>
+    where
+        E: FnOnce() -> WeightImage,
+        S: IntoIterator<Item = Vector2<i32>>,
+    {
+        let _timer = Timer::start("record_frame");
+
+        let panels = if self.energy_panel { 2 } else { 1 };
+        let mut frame = RgbaImage::from_pixel(
+            (self.canvas.x * panels) as u32,
+            self.canvas.y as u32,
+            Self::BACKGROUND_COLOR,
+        );
+
+        // Draw the image and its energy side by side.
+        let seams = seams.into_iter().collect::<Vec<_>>();
+        let mut draw = |offset: Vector2<i32>, pixel: &dyn Fn(Vector2<i32>) -> Rgba<u8>| {
+            for index in 0..image.dim() {
+                let pos = image.decode_pos(index);
+                frame.put(offset + pos, pixel(pos));
+            }
+            for pos in &seams {
+                frame.put(offset + pos, Self::SEAM_COLOR);
+            }
+        };
+
+        draw(Vector2::new(0, 0), &|pos| vec4_to_rgba(image.color(pos)));
+        if self.energy_panel {
+            let energy = luma_to_rgba(&energy());
+            draw(Vector2::new(self.canvas.x, 0), &|pos| *energy.get(pos));
+        }
+
+        self.encoder
+            .encode_frame(Frame::from_parts(
+                frame,
+                0,
+                0,
+                Delay::from_numer_denom_ms(delay_ms, 1),
+            ))
+            .writing(&self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::carver::FinderOptions;
+    use crate::energy::ColorSpace;
+    use image::imageops::thumbnail;
+    use std::collections::HashSet;
+
+    fn open_thumbnail(width: u32, height: u32) -> RgbaImage {
+        let path = format!("{}/images/cat.png", env!("CARGO_MANIFEST_DIR"));
+        thumbnail(&open(path).unwrap().into_rgba8(), width, height)
+    }
+
+    #[test]
+    fn frame_patterns_are_parsed() {
+        assert_eq!(
+            split_frame_pattern("frames/%d.png"),
+            Some(("frames/", 0, ".png"))
+        );
+        assert_eq!(split_frame_pattern("f%04d_x.png"), Some(("f", 4, "_x.png")));
+        assert_eq!(frame_path("frames/%03d.png", 7), "frames/007.png");
+        assert_eq!(frame_path("frames/%d.png", 1234), "frames/1234.png");
+
+        for path in ["frames/cat.png", "100%.png", "%4d.png", "%x.png", "%"] {
+            assert!(!is_frame_pattern(path), "{:?} is not a frame pattern", path);
+        }
+        assert!(is_video_path("cat.GIF"));
+        assert!(!is_video_path("cat.png"));
+    }
+
+    #[test]
+    fn videos_survive_a_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let frames = vec![open_thumbnail(24, 20), open_thumbnail(24, 20)];
+        let mut video = Video {
+            delays: vec![Delay::from_numer_denom_ms(DEFAULT_FRAME_DELAY_MS, 1); 2],
+            frames,
+            first_index: 1,
+        };
+        video.frames[1].put_pixel(3, 4, Rgba([1, 2, 3, 255]));
+
+        // Frame sequences are lossless and keep their numbering.
+        let pattern = dir.path().join("frame-%02d.png");
+        let pattern = pattern.to_str().unwrap();
+        save_video(pattern, &video).unwrap();
+        assert!(dir.path().join("frame-01.png").exists());
+        let loaded = load_video(pattern).unwrap();
+        assert_eq!(loaded.first_index, 1);
+        assert_eq!(loaded.frames, video.frames);
+
+        // GIFs quantize their colors but keep the frames and their delays.
+        let gif = dir.path().join("video.gif");
+        let gif = gif.to_str().unwrap();
+        save_video(gif, &video).unwrap();
+        let loaded = load_video(gif).unwrap();
+        assert_eq!(loaded.frames.len(), 2);
+        assert_eq!(loaded.frames[0].dimensions(), (24, 20));
+        assert_eq!(loaded.delays, video.delays);
+    }
+
+    #[test]
+    fn coherence_reduces_seam_drift() {
+        // Every frame is the same image with a little noise on top. The alpha channel, which the
+        // energy ignores, records the original column of every pixel so that the carved seams can
+        // be recovered from the output.
+        let image = open_thumbnail(48, 40);
+        let frames = (0..6u32)
+            .map(|frame| {
+                RgbaImage::from_fn(48, 40, |x, y| {
+                    let noise = (x * 31 + y * 17 + frame * 71) % 23;
+                    let Rgba([r, g, b, _]) = *image.get_pixel(x, y);
+                    let noisy = |c: u8| c.saturating_add(noise as u8);
+                    Rgba([noisy(r), noisy(g), noisy(b), x as u8])
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let finder = FinderOptions {
+            color: ColorSpace::Rgb,
+            ..Default::default()
+        }
+        .build()
+        .unwrap();
+
+        // The number of pixels carved out of one frame but not the next.
+        let drift = |coherence: f32| {
+            let mut video = Video {
+                delays: vec![Delay::from_numer_denom_ms(DEFAULT_FRAME_DELAY_MS, 1); frames.len()],
+                frames: frames.clone(),
+                first_index: 0,
+            };
+            let steps = vec![Axis::Vertical; 12];
+            carve_video(&mut video, &finder, &steps, &Masks::default(), coherence);
+
+            let carved = video
+                .frames
+                .iter()
+                .map(|frame| {
+                    (0..40)
+                        .flat_map(|y| {
+                            let kept = (0..frame.width())
+                                .map(|x| frame.get_pixel(x, y).0[3] as u32)
+                                .collect::<HashSet<_>>();
+                            (0..48)
+                                .filter(move |x| !kept.contains(x))
+                                .map(move |x| (x, y))
+                        })
+                        .collect::<HashSet<_>>()
+                })
+                .collect::<Vec<_>>();
+            carved
+                .windows(2)
+                .map(|pair| pair[0].difference(&pair[1]).count())
+                .sum::<usize>()
+        };
+
+        let (free, coherent) = (drift(0.), drift(DEFAULT_COHERENCE));
+        assert!(2 * coherent < free, "{} vs {}", coherent, free);
+    }
+
+    #[test]
+    fn animations_must_record_some_passes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("animation.gif");
+        let path = path.to_str().unwrap();
+        let recorder = AnimationRecorder::new(path, Vector2::new(4, 4), 0, false);
+        assert!(matches!(recorder, Err(CarveError::InvalidInput(_))));
+        assert!(AnimationRecorder::new(path, Vector2::new(4, 4), 1, false).is_ok());
+    }
+}